@@ -0,0 +1,643 @@
+//! Expressions inside `${{ }}`, e.g. `image-${{ env.TAG || 'latest' }}`.
+//!
+//! An expression is made of paths (`request.headers.x-github-event`), string literals in single
+//! quotes (`'it''s'`), numbers, `true`, `false`, `null`, the operators `!`, `==`, `!=`, `<`, `<=`,
+//! `>`, `>=`, `&&`, `||` and parentheses. Like in GitHub Actions `a || b` evaluates to `a` if it
+//! is truthy and to `b` otherwise, which is used for defaults.
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer};
+
+use crate::vars::Resolvers;
+
+pub const EXPRESSION_PREFIX: &str = "${{";
+pub const EXPRESSION_SUFFIX: &str = "}}";
+
+/// The roots of the paths that are only known while the steps of a route run, every other
+/// root is resolved by one of the [`Resolvers`].
+pub const RUNTIME_ROOTS: [&str; 3] = ["request", "route", "steps"];
+
+/// The first segment of every path in an expression.
+pub fn known_roots() -> impl Iterator<Item = &'static str> {
+    Resolvers::global().roots().chain(RUNTIME_ROOTS)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+}
+
+impl Value {
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Null => false,
+            Value::Bool(value) => *value,
+            Value::Number(value) => *value != 0.0,
+            Value::String(value) => !value.is_empty(),
+        }
+    }
+
+    fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Null => Some(0.0),
+            Value::Bool(value) => Some(if *value { 1.0 } else { 0.0 }),
+            Value::Number(value) => Some(*value),
+            Value::String(value) if value.trim().is_empty() => Some(0.0),
+            Value::String(value) => value.trim().parse().ok(),
+        }
+    }
+
+    /// Compares like GitHub Actions: strings case-insensitive, everything else as numbers.
+    fn compare(&self, other: &Value) -> Option<std::cmp::Ordering> {
+        match (self, other) {
+            (Value::String(left), Value::String(right)) => {
+                Some(left.to_lowercase().cmp(&right.to_lowercase()))
+            }
+            _ => self.as_number()?.partial_cmp(&other.as_number()?),
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Null => Ok(()),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Number(value) => write!(f, "{}", value),
+            Value::String(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl From<serde_json::Value> for Value {
+    fn from(value: serde_json::Value) -> Self {
+        match value {
+            serde_json::Value::Null => Value::Null,
+            serde_json::Value::Bool(value) => Value::Bool(value),
+            serde_json::Value::Number(value) => value.as_f64().map_or(Value::Null, Value::Number),
+            serde_json::Value::String(value) => Value::String(value),
+            value => Value::String(value.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Literal(Value),
+    Path(Vec<String>),
+    Not(Box<Expression>),
+    Binary(Operator, Box<Expression>, Box<Expression>),
+}
+
+impl Expression {
+    /// Calls `f` with every path in the expression.
+    fn visit_paths<'a>(&'a self, f: &mut impl FnMut(&'a [String])) {
+        match self {
+            Expression::Literal(_) => {}
+            Expression::Path(path) => f(path),
+            Expression::Not(inner) => inner.visit_paths(f),
+            Expression::Binary(_, left, right) => {
+                left.visit_paths(f);
+                right.visit_paths(f);
+            }
+        }
+    }
+
+    /// Evaluates the expression, `lookup` resolves paths like `env.TAG` to their value.
+    pub fn evaluate(&self, lookup: &dyn Fn(&[String]) -> Result<Value>) -> Result<Value> {
+        Ok(match self {
+            Expression::Literal(value) => value.clone(),
+            Expression::Path(path) => lookup(path)?,
+            Expression::Not(inner) => Value::Bool(!inner.evaluate(lookup)?.is_truthy()),
+            Expression::Binary(Operator::Or, left, right) => {
+                let left = left.evaluate(lookup)?;
+                if left.is_truthy() {
+                    left
+                } else {
+                    right.evaluate(lookup)?
+                }
+            }
+            Expression::Binary(Operator::And, left, right) => {
+                let left = left.evaluate(lookup)?;
+                if left.is_truthy() {
+                    right.evaluate(lookup)?
+                } else {
+                    left
+                }
+            }
+            Expression::Binary(operator, left, right) => {
+                use std::cmp::Ordering::*;
+
+                let ordering = left.evaluate(lookup)?.compare(&right.evaluate(lookup)?);
+                Value::Bool(match operator {
+                    Operator::Eq => ordering == Some(Equal),
+                    Operator::Ne => ordering != Some(Equal),
+                    Operator::Lt => ordering == Some(Less),
+                    Operator::Le => matches!(ordering, Some(Less | Equal)),
+                    Operator::Gt => ordering == Some(Greater),
+                    Operator::Ge => matches!(ordering, Some(Greater | Equal)),
+                    Operator::And | Operator::Or => unreachable!(),
+                })
+            }
+        })
+    }
+}
+
+/// An error while parsing a template, `column` is the position in the template starting at 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub column: usize,
+    pub message: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at column {}", self.message, self.column)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+struct Parser<'a> {
+    source: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, ParseError> {
+        Err(ParseError {
+            column: self.source[..self.position].chars().count() + 1,
+            message: message.into(),
+        })
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.source[self.position..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    /// Consumes `token` after optional whitespace.
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+
+        if self.rest().starts_with(token) {
+            self.position += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> &'a str {
+        let rest = self.rest();
+        let len = rest.find(|c| !predicate(c)).unwrap_or(rest.len());
+        self.position += len;
+
+        &rest[..len]
+    }
+
+    fn or(&mut self) -> Result<Expression, ParseError> {
+        let mut left = self.and()?;
+        while self.eat("||") {
+            left = Expression::Binary(Operator::Or, Box::new(left), Box::new(self.and()?));
+        }
+
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expression, ParseError> {
+        let mut left = self.comparison()?;
+        while self.eat("&&") {
+            left = Expression::Binary(Operator::And, Box::new(left), Box::new(self.comparison()?));
+        }
+
+        Ok(left)
+    }
+
+    fn comparison(&mut self) -> Result<Expression, ParseError> {
+        let left = self.unary()?;
+
+        // longer operators first, so that `<=` isn't read as `<`
+        let operators = [
+            ("==", Operator::Eq),
+            ("!=", Operator::Ne),
+            ("<=", Operator::Le),
+            (">=", Operator::Ge),
+            ("<", Operator::Lt),
+            (">", Operator::Gt),
+        ];
+        for (token, operator) in operators {
+            if self.eat(token) {
+                return Ok(Expression::Binary(
+                    operator,
+                    Box::new(left),
+                    Box::new(self.unary()?),
+                ));
+            }
+        }
+
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expression, ParseError> {
+        if self.eat("!") {
+            return Ok(Expression::Not(Box::new(self.unary()?)));
+        }
+
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expression, ParseError> {
+        self.skip_whitespace();
+
+        if self.eat("(") {
+            let inner = self.or()?;
+            if !self.eat(")") {
+                return self.error("Expected ')'");
+            }
+
+            return Ok(inner);
+        }
+
+        let start = self.position;
+
+        if self.eat("'") {
+            let mut value = String::new();
+            loop {
+                let Some(end) = self.rest().find('\'') else {
+                    self.position = start;
+                    return self.error("Unterminated string");
+                };
+
+                value.push_str(&self.rest()[..end]);
+                self.position += end + 1;
+
+                // `''` is an escaped quote
+                if !self.rest().starts_with('\'') {
+                    break;
+                }
+                value.push('\'');
+                self.position += 1;
+            }
+
+            return Ok(Expression::Literal(Value::String(value)));
+        }
+
+        let number = self.take_while(|c| c.is_ascii_digit() || c == '.');
+        if !number.is_empty() {
+            return match number.parse() {
+                Ok(number) => Ok(Expression::Literal(Value::Number(number))),
+                Err(_) => {
+                    self.position = start;
+                    self.error(format!("Invalid number '{}'", number))
+                }
+            };
+        }
+
+        let ident = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+        match ident {
+            "" => match self.rest().chars().next() {
+                Some(c) => self.error(format!("Unexpected '{}'", c)),
+                None => self.error("Expected an expression"),
+            },
+            "true" => Ok(Expression::Literal(Value::Bool(true))),
+            "false" => Ok(Expression::Literal(Value::Bool(false))),
+            "null" => Ok(Expression::Literal(Value::Null)),
+            ident => {
+                if !known_roots().any(|root| root == ident) {
+                    self.position = start;
+                    return self.error(format!(
+                        "Unknown variable '{}', expected one of: {}",
+                        ident,
+                        known_roots().collect::<Vec<_>>().join(", ")
+                    ));
+                }
+
+                if Resolvers::global().takes_raw_path(ident) {
+                    if !self.rest().starts_with('.') {
+                        return self.error(format!("Expected '.' after '{}'", ident));
+                    }
+                    self.position += 1;
+
+                    let raw = self.take_while(|c| !c.is_whitespace() && c != ')' && c != '}');
+                    if raw.is_empty() {
+                        return self.error("Expected a path after '.'");
+                    }
+                    return Ok(Expression::Path(vec![ident.to_string(), raw.to_string()]));
+                }
+
+                let mut path = vec![ident.to_string()];
+                while self.rest().starts_with('.') {
+                    self.position += 1;
+
+                    let segment =
+                        self.take_while(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+                    if segment.is_empty() {
+                        return self.error("Expected a name after '.'");
+                    }
+                    path.push(segment.to_string());
+                }
+
+                Ok(Expression::Path(path))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Expression {
+        source: String,
+        expression: Expression,
+    },
+}
+
+/// A string that can contain expressions, e.g. `image-${{ env.TAG }}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Template, ParseError> {
+        let mut parser = Parser {
+            source: template,
+            position: 0,
+        };
+        let mut segments = Vec::new();
+
+        while let Some(start) = parser.rest().find(EXPRESSION_PREFIX) {
+            if start > 0 {
+                segments.push(Segment::Literal(parser.rest()[..start].to_string()));
+            }
+            parser.position += start + EXPRESSION_PREFIX.len();
+
+            let source_start = parser.position;
+            let expression = parser.or()?;
+            let source = parser.source[source_start..parser.position]
+                .trim()
+                .to_string();
+
+            if !parser.eat(EXPRESSION_SUFFIX) {
+                return match parser.rest().is_empty() {
+                    true => parser.error(format!("Expected '{}'", EXPRESSION_SUFFIX)),
+                    false => parser.error(format!(
+                        "Unexpected '{}'",
+                        parser.rest().split_whitespace().next().unwrap_or_default()
+                    )),
+                };
+            }
+
+            segments.push(Segment::Expression { source, expression });
+        }
+
+        if !parser.rest().is_empty() {
+            segments.push(Segment::Literal(parser.rest().to_string()));
+        }
+
+        Ok(Template { segments })
+    }
+
+    /// `true` if the template doesn't contain any expression.
+    pub fn is_literal(&self) -> bool {
+        self.segments
+            .iter()
+            .all(|segment| matches!(segment, Segment::Literal(_)))
+    }
+
+    /// All paths referenced by the expressions of the template.
+    pub fn paths(&self) -> Vec<&[String]> {
+        let mut paths = Vec::new();
+
+        for segment in &self.segments {
+            if let Segment::Expression { expression, .. } = segment {
+                expression.visit_paths(&mut |path| paths.push(path));
+            }
+        }
+
+        paths
+    }
+
+    /// `true` if every path of the template starts with `root`.
+    pub fn only_references(&self, root: &str) -> bool {
+        self.paths().iter().all(|path| path[0] == root)
+    }
+
+    /// Evaluates every expression and joins the results, an expression that evaluates to `null`
+    /// is an error, use a default like `env.TAG || ''` for optional values.
+    pub fn render(&self, lookup: &dyn Fn(&[String]) -> Result<Value>) -> Result<String> {
+        let mut rendered = String::new();
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => rendered.push_str(literal),
+                Segment::Expression { source, expression } => match expression.evaluate(lookup)? {
+                    Value::Null => bail!("The expression '{}' has no value", source),
+                    value => rendered.push_str(&value.to_string()),
+                },
+            }
+        }
+
+        Ok(rendered)
+    }
+}
+
+impl FromStr for Template {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Template::parse(s)
+    }
+}
+
+/// Checks the expressions of `value` while deserializing, so that the error contains the location
+/// in the config file, which serde_yaml only adds to the errors of a visitor.
+pub(crate) fn check_template<E: de::Error>(value: &str) -> Result<(), E> {
+    Template::parse(value).map(|_| ()).map_err(|err| {
+        E::custom(format!(
+            "Invalid expression '{}' ({} at character {})",
+            value, err.message, err.column
+        ))
+    })
+}
+
+/// A string whose expressions are checked while deserializing, so that the error
+/// contains the location in the config file.
+#[derive(Debug)]
+struct Checked(String);
+
+impl<'de> Deserialize<'de> for Checked {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct CheckedVisitor;
+
+        impl<'de> Visitor<'de> for CheckedVisitor {
+            type Value = Checked;
+
+            fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+                formatter.write_str("a string")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                check_template(value)?;
+
+                Ok(Checked(value.to_string()))
+            }
+        }
+
+        deserializer.deserialize_str(CheckedVisitor)
+    }
+}
+
+pub(crate) fn deserialize_checked<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<String, D::Error> {
+    Checked::deserialize(deserializer).map(|checked| checked.0)
+}
+
+pub(crate) fn deserialize_checked_option<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    Option::<Checked>::deserialize(deserializer).map(|checked| checked.map(|checked| checked.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn lookup(path: &[String]) -> Result<Value> {
+        Ok(match path.join(".").as_str() {
+            "env.TAG" => Value::String("v1".to_string()),
+            "request.headers.x-github-event" => Value::String("push".to_string()),
+            "steps.build.outputs.count" => Value::String("3".to_string()),
+            _ => Value::Null,
+        })
+    }
+
+    fn render(template: &str) -> Result<String> {
+        Template::parse(template)?.render(&lookup)
+    }
+
+    #[test]
+    fn interpolates_expressions() {
+        assert_eq!(render("image-${{ env.TAG }}").unwrap(), "image-v1");
+        assert_eq!(
+            render("${{env.TAG}}: ${{ request.headers.x-github-event }}!").unwrap(),
+            "v1: push!"
+        );
+        assert_eq!(render("no expression").unwrap(), "no expression");
+        assert_eq!(
+            Template::parse("${{ file./run/secrets/github.token || env.TAG }}")
+                .unwrap()
+                .paths(),
+            [
+                &["file".to_string(), "/run/secrets/github.token".to_string()][..],
+                &["env".to_string(), "TAG".to_string()][..]
+            ]
+        );
+    }
+
+    #[test]
+    fn raw_paths_end_before_the_closing_braces() {
+        assert_eq!(
+            Template::parse("${{file./run/secrets/github}}-${{ credentials.github.token}}")
+                .unwrap()
+                .paths(),
+            [
+                &["file".to_string(), "/run/secrets/github".to_string()][..],
+                &["credentials".to_string(), "github.token".to_string()][..]
+            ]
+        );
+    }
+
+    #[test]
+    fn evaluates_defaults_and_comparisons() {
+        assert_eq!(
+            render("${{ env.MISSING || 'fallback' }}").unwrap(),
+            "fallback"
+        );
+        assert_eq!(render("${{ env.TAG || 'fallback' }}").unwrap(), "v1");
+        assert_eq!(render("${{ 'it''s' }}").unwrap(), "it's");
+        assert_eq!(
+            render("${{ request.headers.x-github-event == 'PUSH' }}").unwrap(),
+            "true"
+        );
+        assert_eq!(
+            render("${{ steps.build.outputs.count >= 3 && !(env.TAG != 'v1') }}").unwrap(),
+            "true"
+        );
+        assert_eq!(
+            render("${{ steps.build.outputs.count < 2 }}").unwrap(),
+            "false"
+        );
+    }
+
+    #[test]
+    fn null_is_an_error() {
+        assert_eq!(
+            render("${{ env.MISSING }}").unwrap_err().to_string(),
+            "The expression 'env.MISSING' has no value"
+        );
+    }
+
+    #[test]
+    fn reports_parse_errors() {
+        let error = |template| Template::parse(template).unwrap_err().to_string();
+
+        assert_eq!(error("a ${{ env.TAG"), "Expected '}}' at column 14");
+        assert_eq!(
+            error("${{ secret.TAG }}"),
+            "Unknown variable 'secret', expected one of: env, file, credentials, request, route, steps at column 5"
+        );
+        assert_eq!(error("${{ 'open }}"), "Unterminated string at column 5");
+        assert_eq!(
+            error("${{ env. }}"),
+            "Expected a name after '.' at column 9"
+        );
+        assert_eq!(
+            error("${{ env.A env.B }}"),
+            "Unexpected 'env.B' at column 11"
+        );
+    }
+
+    #[test]
+    fn reports_location_in_config() {
+        #[derive(Debug, Deserialize)]
+        struct Step {
+            #[allow(dead_code)]
+            with: HashMap<String, Checked>,
+        }
+
+        let err = serde_yaml::from_str::<Step>("with:\n  url: ${{ env.URL\n").unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "with.url: Invalid expression '${{ env.URL' (Expected '}}' at character 12) at line 2 column 8"
+        );
+    }
+}
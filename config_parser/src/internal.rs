@@ -1,163 +1,109 @@
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
 
 use anyhow::{Context, Result};
 use cron::Schedule;
 use derivative::Derivative;
-use glue::error::CustomError;
-use glue::exports::fct_setup;
-use tokio::sync::Mutex;
-use uuid::Uuid;
-use wasmtime::{Engine, Instance, Linker, Module, Store};
-use wasmtime_wasi::{WasiCtxBuilder, WasiP1Ctx};
+use glue::plugin::{Plugin, PluginLimits, PluginLoader};
 
 use crate::raw::{Config, ConfigFile, ConfigVersion, Route, Step};
-
-#[derive(Debug)]
-enum Variable<'a> {
-    Env(&'a str),
-}
-
-trait ReplaceVariables {
-    const VARIABLE_PREFIX: &'static str = "${{";
-    const VARIABLE_SUFFIX: &'static str = "}}";
-
-    fn is_variable(value: &str) -> bool {
-        Self::get_inner(value).is_some()
-    }
-
-    fn get_inner<'a>(value: &'a str) -> Option<Variable<'a>> {
-        value
-            .strip_prefix(Self::VARIABLE_PREFIX)
-            .map(|item| item.strip_suffix(Self::VARIABLE_SUFFIX))
-            .flatten()
-            .map(|item| item.trim())
-            .map(|item| {
-                if let Some(env_key) = item.strip_prefix("env.") {
-                    Some(Variable::Env(env_key))
-                } else {
-                    todo!("Unknown variable: {:?}", item);
-                }
-            })
-            .flatten()
-    }
-
-    fn replace(&mut self) -> Result<()>;
-}
+use crate::secret::Redactor;
+use crate::value::StepValue;
+use crate::vars::{ReplaceVariables, Resolvers};
 
 #[derive(Derivative, Clone)]
 #[derivative(Debug)]
 pub struct StepInternal {
     pub uses: String,
     pub name: Option<String>,
-    pub with: HashMap<String, String>,
-    pub arguments: HashMap<String, String>,
+    pub with: HashMap<String, StepValue>,
+    pub arguments: HashMap<String, StepValue>,
 
-    pub id: Uuid,
+    /// Used to reference the outputs of the step with `steps.<id>.outputs.<name>`.
+    pub id: Option<String>,
     #[derivative(Debug = "ignore")]
-    pub instance: Option<Arc<Instance>>,
-    #[derivative(Debug = "ignore")]
-    pub store: Option<Arc<Mutex<Store<WasiP1Ctx>>>>,
+    pub plugin: Option<Plugin>,
 }
 
 impl StepInternal {
-    async fn from_step(value: Step) -> Result<StepInternal> {
+    /// Name of the step for logs and reports, falls back to `uses` if no name is set.
+    pub fn display_name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.uses)
+    }
+
+    /// Redacts the secrets of `with` and `arguments` from the logs and errors of the step.
+    pub fn redactor(&self) -> Redactor {
+        Redactor::new(
+            self.with
+                .values()
+                .chain(self.arguments.values())
+                .flat_map(StepValue::revealed_secrets)
+                .map(str::to_string),
+        )
+    }
+
+    async fn from_step(value: Step, loader: &mut PluginLoader) -> Result<StepInternal> {
         let mut step = StepInternal {
             uses: value.uses,
             name: value.name,
             with: value.with,
             arguments: value.arguments,
-            id: Uuid::new_v4(),
-            instance: None,
-            store: None,
+            id: value.id,
+            plugin: None,
         };
 
         if let Some(wasm_module) = step.with.get("wasm") {
-            let engine = Engine::new(
-                wasmtime::Config::default()
-                    .async_support(true)
-                    .dynamic_memory_guard_size(1 << 20),
-            )?;
-
-            let mut linker = Linker::new(&engine);
-            wasmtime_wasi::preview1::add_to_linker_async(&mut linker, |s| s)?;
-
-            let wasi = WasiCtxBuilder::new()
-                .inherit_stderr()
-                .inherit_stdout() // TODO map stdout to maybe log and append with something like: "WASM: "
-                .build_p1();
-            let mut store = Store::new(&engine, wasi);
-
-            let module_validator = Module::from_file(&engine, wasm_module)?;
-            linker
-                .module_async(&mut store, &step.id.to_string(), &module_validator)
-                .await?;
-
-            let instance = linker
-                .instantiate_async(&mut store, &module_validator)
-                .await?;
-
-            let instance = Arc::new(instance);
-            let store = Arc::new(Mutex::new(store));
-
-            let fct_setup = fct_setup(instance.clone(), store.clone()).await?;
-            if fct_setup().await? != 0 {
-                let error = CustomError::from_wasm(instance.clone(), store.clone())
-                    .await?
-                    .context("Could not get the error from wasm")?;
-                dbg!(error);
-
-                panic!("Can't init the wasm module");
-            }
-
-            step.instance = Some(instance);
-            step.store = Some(store);
+            let wasm_module = wasm_module.reveal_str().with_context(|| {
+                format!("`wasm` of the step '{}' is not a path", step.display_name())
+            })?;
+            let limits = plugin_limits(&step.with).with_context(|| {
+                format!("Invalid limits for the step '{}'", step.display_name())
+            })?;
+
+            step.plugin = Some(loader.load(wasm_module, limits).await?);
         }
 
         Ok(step)
     }
 }
 
-impl ReplaceVariables for StepInternal {
-    fn replace(&mut self) -> Result<()> {
-        if Self::is_variable(&self.uses) {
-            todo!("uses")
-        }
-
-        if let Some(name) = &mut self.name {
-            if Self::is_variable(&name) {
-                todo!("name")
-            }
-        }
-
-        self.with.values_mut().for_each(|item| {
-            if Self::is_variable(&item) {
-                todo!("with")
-            }
-        });
-
-        for argument in self.arguments.values_mut() {
-            if let Some(inner_variable) = Self::get_inner(&argument) {
-                let replace_with = match inner_variable {
-                    Variable::Env(env_key) => std::env::var(env_key).with_context(|| {
-                        format!(
-                            "Could not find an environment variable with the name: '{:?}'",
-                            env_key
-                        )
-                    })?,
-                };
-
-                *argument = replace_with;
-            }
-        }
+fn parse_with<T>(with: &HashMap<String, StepValue>, key: &str) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    with.get(key)
+        .map(|value| {
+            value
+                .reveal_scalar()
+                .unwrap_or_default()
+                .parse()
+                .with_context(|| format!("'{}' is not a valid value for '{}'", value, key))
+        })
+        .transpose()
+}
 
-        Ok(())
-    }
+/// Reads `timeout_ms`, `max_memory` (in bytes) and `max_table_elements` from the `with` map
+/// of a step, every key that isn't set falls back to [`PluginLimits::default`].
+fn plugin_limits(with: &HashMap<String, StepValue>) -> Result<PluginLimits> {
+    let default = PluginLimits::default();
+
+    Ok(PluginLimits {
+        timeout: parse_with(with, "timeout_ms")?
+            .map(Duration::from_millis)
+            .unwrap_or(default.timeout),
+        max_memory: parse_with(with, "max_memory")?.unwrap_or(default.max_memory),
+        max_table_elements: parse_with(with, "max_table_elements")?
+            .unwrap_or(default.max_table_elements),
+    })
 }
 
 #[derive(Debug, Clone)]
 pub struct HealthCheckInternal {
     pub period: Schedule, // TODO the struct `Schedule` is really large, maybe box or rc/arc it?
+    pub path: Option<String>,
     pub steps: Vec<StepInternal>,
 }
 
@@ -169,15 +115,15 @@ pub struct RouteInternal {
 }
 
 impl RouteInternal {
-    async fn from_route(value: Route) -> Result<RouteInternal> {
+    async fn from_route(value: Route, loader: &mut PluginLoader) -> Result<RouteInternal> {
         let mut pipeline_internal = Vec::with_capacity(value.pipeline.len());
         for pipeline in value.pipeline {
-            pipeline_internal.push(StepInternal::from_step(pipeline).await?);
+            pipeline_internal.push(StepInternal::from_step(pipeline, loader).await?);
         }
 
         let mut steps = Vec::with_capacity(value.steps.len());
         for step in value.steps {
-            steps.push(StepInternal::from_step(step).await?);
+            steps.push(StepInternal::from_step(step, loader).await?);
         }
 
         Ok(RouteInternal {
@@ -193,45 +139,122 @@ pub struct ConfigFileInternal {
     pub version: ConfigVersion,
     pub config: Config,
     pub health_check: Option<HealthCheckInternal>,
-    pub route: RouteInternal,
+    pub routes: Vec<RouteInternal>,
 }
 
 impl ConfigFileInternal {
-    pub fn populate_env_variables(&mut self) -> Result<()> {
-        self.route
-            .pipeline
-            .iter_mut()
-            .for_each(|item| item.replace().unwrap());
-
-        self.route
-            .steps
-            .iter_mut()
-            .for_each(|item| item.replace().unwrap());
-
-        Ok(())
+    /// Loads the config file at `path` with [`ConfigFile::load`] and instantiates its plugins
+    /// with [`ConfigFileInternal::from_config`].
+    pub async fn load(path: impl AsRef<Path>, actions: &[&str]) -> Result<ConfigFileInternal> {
+        ConfigFileInternal::from_config(ConfigFile::load(path, actions)?).await
     }
 
-    pub async fn from_config(value: ConfigFile) -> Result<ConfigFileInternal> {
+    /// Paths of the wasm plugins of all steps.
+    pub fn wasm_paths(&self) -> Vec<PathBuf> {
+        let health_check_steps = self
+            .health_check
+            .iter()
+            .flat_map(|health_check| &health_check.steps);
+        let route_steps = self
+            .routes
+            .iter()
+            .flat_map(|route| route.pipeline.iter().chain(&route.steps));
+
+        health_check_steps
+            .chain(route_steps)
+            .filter_map(|step| step.with.get("wasm")?.reveal_str())
+            .map(PathBuf::from)
+            .collect()
+    }
+
+    /// Replaces the variables of all steps first and instantiates the wasm plugins afterwards,
+    /// so that the plugins already see the final values, e.g. the path of the plugin.
+    pub async fn from_config(mut value: ConfigFile) -> Result<ConfigFileInternal> {
+        value.replace_variables(Resolvers::global())?;
+        let mut loader = PluginLoader::new(value.config.cache_dir.clone())?;
+
         let health_check = if let Some(health_check) = value.health_check {
             let mut steps_internal = Vec::with_capacity(health_check.steps.len());
 
             for step in health_check.steps {
-                steps_internal.push(StepInternal::from_step(step).await?);
+                steps_internal.push(StepInternal::from_step(step, &mut loader).await?);
             }
 
             Some(HealthCheckInternal {
                 period: health_check.period,
+                path: health_check.path,
                 steps: steps_internal,
             })
         } else {
             None
         };
 
+        let mut routes = Vec::with_capacity(value.routes.len());
+        for route in value.routes {
+            routes.push(RouteInternal::from_route(route, &mut loader).await?);
+        }
+
         Ok(ConfigFileInternal {
             version: value.version,
             config: value.config,
             health_check,
-            route: RouteInternal::from_route(value.route).await?,
+            routes,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+version: 1.0
+config:
+  bind: 0.0.0.0:3000
+health_check:
+  period: "0 5 * * * * *"
+  steps:
+    - uses: health_wasm
+      with:
+        wasm: ${{ env.INTERNAL_TEST_PLUGIN_DIR }}/plugin.wat
+routes:
+  - path: /deploy
+    pipeline:
+      - uses: http_validator_wasm
+        with:
+          wasm: ${{ env.INTERNAL_TEST_PLUGIN_DIR }}/plugin.wat
+    steps:
+      - uses: step_wasm
+        with:
+          wasm: ${{ env.INTERNAL_TEST_PLUGIN_DIR }}/plugin.wat
+"#;
+
+    #[tokio::test]
+    async fn resolves_variables_before_loading_plugins() {
+        let dir = std::env::temp_dir().join(format!("config_parser_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("plugin.wat"),
+            r#"(module (func (export "_setup") (result i32) (i32.const 0)))"#,
+        )
+        .unwrap();
+        std::env::set_var("INTERNAL_TEST_PLUGIN_DIR", &dir);
+
+        let config = ConfigFileInternal::from_config(serde_yaml::from_str(CONFIG).unwrap())
+            .await
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let health_check = config.health_check.unwrap();
+        let route = &config.routes[0];
+        for step in [&health_check.steps[0], &route.pipeline[0], &route.steps[0]] {
+            assert_eq!(
+                step.with["wasm"].reveal_str().unwrap(),
+                dir.join("plugin.wat").display().to_string(),
+                "step '{}'",
+                step.display_name()
+            );
+            assert!(step.plugin.is_some(), "step '{}'", step.display_name());
+        }
+    }
+}
@@ -1,2 +1,10 @@
+pub mod expression;
 pub mod internal;
+pub mod migrate;
 pub mod raw;
+pub mod schema;
+pub mod secret;
+pub mod validate;
+pub mod value;
+pub mod vars;
+mod yaml;
@@ -0,0 +1,369 @@
+//! Migration of config files of older versions to [`ConfigVersion::LATEST`].
+//!
+//! The migrations rewrite the source of the config file instead of the parsed values, so that
+//! `webhook_handler migrate` keeps the comments and the formatting. The same rewrite is done in
+//! memory whenever a config file of an older version is loaded.
+
+use std::ops::Range;
+
+use anyhow::{bail, Context, Result};
+
+use crate::raw::ConfigVersion;
+use crate::validate::Problem;
+use crate::yaml::{self, Kind, Node};
+
+/// Renames the key `from` of the map at `path` to `to`, the value is converted with `value`.
+struct Rename {
+    path: &'static [&'static str],
+    from: &'static str,
+    to: &'static str,
+    value: fn(&str) -> String,
+}
+
+/// Replaces the map in the key `from` of the root map with a list that contains the map as its
+/// only item in the key `to`.
+struct IntoList {
+    from: &'static str,
+    to: &'static str,
+}
+
+/// The changes from the version `from` to the version `to`.
+struct Migration {
+    from: ConfigVersion,
+    to: ConfigVersion,
+    renames: &'static [Rename],
+    lists: &'static [IntoList],
+}
+
+const MIGRATIONS: [Migration; 1] = [Migration {
+    from: ConfigVersion::V1_0Beta,
+    to: ConfigVersion::V1_0,
+    renames: &[
+        Rename {
+            path: &["config"],
+            from: "expose",
+            to: "bind",
+            value: |port| format!("0.0.0.0:{}", port),
+        },
+        // `url` was ignored by 1.0-beta, but it is what the demo config used for `uri`
+        Rename {
+            path: &["config"],
+            from: "url",
+            to: "uri",
+            value: str::to_string,
+        },
+    ],
+    lists: &[IntoList {
+        from: "route",
+        to: "routes",
+    }],
+}];
+
+/// A config file that was migrated to [`ConfigVersion::LATEST`].
+#[derive(Debug)]
+pub struct Migrated {
+    pub source: String,
+    /// Everything that was migrated, located in the original source.
+    pub deprecations: Vec<Problem>,
+}
+
+impl Migrated {
+    pub fn is_migrated(&self) -> bool {
+        !self.deprecations.is_empty()
+    }
+}
+
+/// Migrates the source of a config file to the latest version, a config file with an invalid
+/// version or syntax is returned as it is and left to the validation.
+pub fn migrate(source: &str) -> Result<Migrated> {
+    let mut migrated = Migrated {
+        source: source.to_string(),
+        deprecations: Vec::new(),
+    };
+
+    while let Some(root) = yaml::parse(&migrated.source).ok().flatten() {
+        let Some((_, version)) = root.entry("version") else {
+            break;
+        };
+        let Some(migration) = version
+            .as_str()
+            .and_then(|version| version.parse::<ConfigVersion>().ok())
+            .and_then(|version| {
+                MIGRATIONS
+                    .iter()
+                    .find(|migration| migration.from == version)
+            })
+        else {
+            break;
+        };
+
+        if migrated.deprecations.is_empty() {
+            migrated.deprecations.push(deprecation(
+                version,
+                format!(
+                    "Version {} is deprecated, the config file can be upgraded to {} with `webhook_handler migrate`",
+                    migration.from,
+                    ConfigVersion::LATEST
+                ),
+            ));
+        }
+
+        let mut edits = vec![replace(
+            &migrated.source,
+            version,
+            &migration.to.to_string(),
+        )?];
+        for rename in migration.renames {
+            let Some((key, value)) = rename
+                .path
+                .iter()
+                .try_fold(&root, |node, key| node.entry(key).map(|(_, value)| value))
+                .filter(|map| map.entry(rename.to).is_none())
+                .and_then(|map| map.entry(rename.from))
+            else {
+                continue;
+            };
+
+            let path = rename.path.join(".");
+            let new_value = (rename.value)(value.as_str().with_context(|| {
+                format!("`{}.{}` must be a string or a number", path, rename.from)
+            })?);
+            migrated.deprecations.push(deprecation(
+                key,
+                format!(
+                    "`{}.{}` is deprecated since {}, use `{}.{}: {}` instead",
+                    path, rename.from, migration.to, path, rename.to, new_value
+                ),
+            ));
+
+            edits.push(replace(&migrated.source, key, rename.to)?);
+            edits.push(replace(&migrated.source, value, &new_value)?);
+        }
+        for list in migration.lists {
+            let Some((key, value)) = root
+                .entry(list.from)
+                .filter(|_| root.entry(list.to).is_none())
+            else {
+                continue;
+            };
+
+            migrated.deprecations.push(deprecation(
+                key,
+                format!(
+                    "`{}` is deprecated since {}, use a list with a single item in `{}` instead",
+                    list.from, migration.to, list.to
+                ),
+            ));
+
+            edits.push(replace(&migrated.source, key, list.to)?);
+            edits.extend(into_list(&migrated.source, value)?);
+        }
+
+        edits.sort_by_key(|(range, _)| range.start);
+        for (range, text) in edits.into_iter().rev() {
+            migrated.source.replace_range(range, &text);
+        }
+    }
+
+    Ok(migrated)
+}
+
+/// Index of the byte of the character at `index`.
+fn byte_index(source: &str, index: usize) -> usize {
+    source
+        .char_indices()
+        .nth(index)
+        .map_or(source.len(), |(index, _)| index)
+}
+
+/// The edits that turn the block map `node` into the only item of a block list, `- ` is put in
+/// front of its first key and all lines of the map are indented by two more spaces.
+fn into_list(source: &str, node: &Node) -> Result<Vec<(Range<usize>, String)>> {
+    let Some((first_key, _)) = (match &node.kind {
+        Kind::Map(entries) => entries.first(),
+        _ => None,
+    }) else {
+        bail!(
+            "Expected a map at line {} column {}",
+            node.line,
+            node.column
+        );
+    };
+
+    let start = byte_index(source, first_key.index);
+    let line_start = source[..start].rfind('\n').map_or(0, |index| index + 1);
+    // the map is on its own lines, otherwise it's a flow map, e.g. `route: { path: /deploy }`
+    if !source[line_start..start].trim().is_empty() {
+        bail!(
+            "Could not rewrite the map at line {} column {}, please migrate the config file by hand",
+            node.line,
+            node.column
+        );
+    }
+
+    let indentation = start - line_start;
+    let mut edits = vec![(start..start, "- ".to_string())];
+    let mut offset = source[start..]
+        .find('\n')
+        .map_or(source.len(), |index| start + index + 1);
+    for line in source[offset..].split_inclusive('\n') {
+        let content = line.trim_start_matches(' ');
+        let line_indentation = line.len() - content.len();
+
+        if line_indentation >= indentation && !content.trim().is_empty() {
+            edits.push((offset..offset, "  ".to_string()));
+        } else if !content.trim().is_empty() && !content.starts_with('#') {
+            // the next key of the parent map
+            break;
+        }
+
+        offset += line.len();
+    }
+
+    Ok(edits)
+}
+
+fn deprecation(node: &Node, message: String) -> Problem {
+    Problem {
+        line: node.line,
+        column: node.column,
+        message,
+    }
+}
+
+/// The range of the scalar `node` in `source` and its replacement with `text`, which is quoted
+/// like the original.
+fn replace(source: &str, node: &Node, text: &str) -> Result<(Range<usize>, String)> {
+    let Kind::Scalar { value, .. } = &node.kind else {
+        bail!(
+            "Expected a string at line {} column {}",
+            node.line,
+            node.column
+        );
+    };
+
+    let start = byte_index(source, node.index);
+    for quote in ["", "'", "\""] {
+        let original = format!("{0}{1}{0}", quote, value);
+        if source[start..].starts_with(&original) {
+            return Ok((
+                start..start + original.len(),
+                format!("{0}{1}{0}", quote, text),
+            ));
+        }
+    }
+
+    bail!(
+        "Could not rewrite '{}' at line {} column {}, please migrate the config file by hand",
+        value,
+        node.line,
+        node.column
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_and_keeps_comments() {
+        let source = r#"# deployed by the webhook handler
+version: '1.0-beta' # the first version
+
+config:
+  # the port of the server
+  expose: 3000
+  uri: https://example.com
+routes: []
+"#;
+
+        let migrated = migrate(source).unwrap();
+
+        assert_eq!(
+            migrated.source,
+            r#"# deployed by the webhook handler
+version: '1.0' # the first version
+
+config:
+  # the port of the server
+  bind: 0.0.0.0:3000
+  uri: https://example.com
+routes: []
+"#
+        );
+        assert_eq!(
+            migrated.deprecations,
+            [
+                Problem {
+                    line: 2,
+                    column: 10,
+                    message: "Version 1.0-beta is deprecated, the config file can be upgraded to 1.0 with `webhook_handler migrate`".to_string(),
+                },
+                Problem {
+                    line: 6,
+                    column: 3,
+                    message: "`config.expose` is deprecated since 1.0, use `config.bind: 0.0.0.0:3000` instead".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn migrates_demo_config_of_beta() {
+        let path = std::env::temp_dir().join(format!("migrate_{}.wat", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "alloc") (param i32) (result i32) (i32.const 0))
+                (func (export "dealloc") (param i32 i32))
+                (func (export "get_err_no") (result i32) (i32.const 0))
+                (func (export "get_err_msg") (result i32) (i32.const 0))
+                (func (export "err_clear"))
+                (func (export "get_response_ptr") (result i32) (i32.const 0))
+                (func (export "get_response_len") (result i32) (i32.const 0))
+                (func (export "_setup") (result i32) (i32.const 0))
+                (func (export "http_validator")
+                    (param i32 i32 i32 i32 i32 i32 i32 i32 i32 i32) (result i32) (i32.const 0)))"#,
+        )
+        .unwrap();
+        let source = include_str!("../tests/demo_config_1.0-beta.yml").replace(
+            "./target/wasm32-wasi/release/github_accept_webhook.wasm",
+            &path.display().to_string(),
+        );
+
+        let migrated = migrate(&source).unwrap();
+        let problems = crate::validate::validate(
+            &migrated.source,
+            &[
+                "docker/ping",
+                "docker/stop_container",
+                "docker/build_image",
+                "docker/start_image",
+            ],
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(problems, []);
+        let config = crate::raw::ConfigFile::parse(&migrated.source).unwrap();
+        assert_eq!(config.routes.len(), 1);
+        assert_eq!(
+            migrated
+                .deprecations
+                .iter()
+                .map(|problem| (problem.line, problem.column))
+                .collect::<Vec<_>>(),
+            [(1, 10), (4, 3), (5, 3), (13, 1)]
+        );
+    }
+
+    #[test]
+    fn keeps_latest_version() {
+        let source = "version: 1.0\nconfig:\n  bind: 127.0.0.1:8080\nroutes: []\n";
+
+        let migrated = migrate(source).unwrap();
+
+        assert_eq!(migrated.source, source);
+        assert!(!migrated.is_migrated());
+    }
+}
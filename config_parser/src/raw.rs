@@ -1,48 +1,35 @@
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
-use std::path::Path;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use anyhow::{bail, Context, Result};
 use cron::Schedule;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{InstanceType, Schema, SchemaObject};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DeserializeFromStr, DisplayFromStr, SerializeDisplay};
-
-#[derive(Debug)]
-enum Variable<'a> {
-    Env(&'a str),
-}
-
-trait ReplaceVariables {
-    const VARIABLE_PREFIX: &'static str = "${{";
-    const VARIABLE_SUFFIX: &'static str = "}}";
-
-    fn is_variable(value: &str) -> bool {
-        Self::get_inner(value).is_some()
-    }
-
-    fn get_inner<'a>(value: &'a str) -> Option<Variable<'a>> {
-        value
-            .strip_prefix(Self::VARIABLE_PREFIX)
-            .map(|item| item.strip_suffix(Self::VARIABLE_SUFFIX))
-            .flatten()
-            .map(|item| item.trim())
-            .map(|item| {
-                if let Some(env_key) = item.strip_prefix("env.") {
-                    Some(Variable::Env(env_key))
-                } else {
-                    todo!("Unknown variable: {:?}", item);
-                }
-            })
-            .flatten()
-    }
-
-    fn replace(&mut self) -> Result<()>;
-}
-
-#[derive(Debug, Clone, SerializeDisplay, DeserializeFromStr)]
+use tracing::warn;
+
+use crate::expression::{deserialize_checked, deserialize_checked_option};
+use crate::migrate::migrate;
+use crate::validate::validate_file;
+use crate::value::StepValue;
+
+/// Version of the config file format, older versions are migrated to [`ConfigVersion::LATEST`]
+/// when the config file is loaded, see [`crate::migrate`].
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, SerializeDisplay, DeserializeFromStr,
+)]
 pub enum ConfigVersion {
     V1_0Beta,
+    V1_0,
+}
+
+impl ConfigVersion {
+    pub const LATEST: ConfigVersion = ConfigVersion::V1_0;
 }
 
 impl Display for ConfigVersion {
@@ -50,6 +37,7 @@ impl Display for ConfigVersion {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             ConfigVersion::V1_0Beta => write!(f, "1.0-beta"),
+            ConfigVersion::V1_0 => write!(f, "1.0"),
         }
     }
 }
@@ -61,108 +49,146 @@ impl FromStr for ConfigVersion {
     fn from_str(s: &str) -> std::prelude::v1::Result<Self, Self::Err> {
         match s {
             "1.0-beta" => Ok(ConfigVersion::V1_0Beta),
+            "1.0" => Ok(ConfigVersion::V1_0),
             _ => bail!("Unknown version: {}", s),
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl JsonSchema for ConfigVersion {
+    fn schema_name() -> String {
+        "ConfigVersion".to_string()
+    }
+
+    /// Only the latest version is allowed, as the schema describes its format. YAML reads an
+    /// unquoted `version: 1.0` as a number, which the parser accepts as well.
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        let latest = ConfigVersion::LATEST.to_string();
+        let mut enum_values = vec![latest.clone().into()];
+        if let Ok(number) = latest.parse::<f64>() {
+            enum_values.push(number.into());
+        }
+
+        SchemaObject {
+            instance_type: Some(vec![InstanceType::String, InstanceType::Number].into()),
+            enum_values: Some(enum_values),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
-    pub expose: u16,
+    /// Address the server listens on, replaces `expose: <port>` of version 1.0-beta.
+    #[serde(default = "default_bind")]
+    pub bind: SocketAddr,
+    /// URI under which the server is reachable from outside.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub uri: Option<String>,
+    /// Directory for the compiled wasm plugins, compiling them on every start is skipped if set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_dir: Option<PathBuf>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+fn default_bind() -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], 3000))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Step {
+    /// Used to reference the outputs of the step with `steps.<id>.outputs.<name>`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(deserialize_with = "deserialize_checked")]
     pub uses: String,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_checked_option",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub name: Option<String>,
-    #[serde(default)]
-    pub with: HashMap<String, String>, // TODO maybe make the value type generic over sth
-    #[serde(default)]
-    pub arguments: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub with: HashMap<String, StepValue>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub arguments: HashMap<String, StepValue>,
 }
 
-impl ReplaceVariables for Step {
-    fn replace(&mut self) -> Result<()> {
-        if Self::is_variable(&self.uses) {
-            todo!("uses")
-        }
-
-        if let Some(name) = &mut self.name {
-            if Self::is_variable(&name) {
-                todo!("name")
-            }
-        }
-
-        self.with.values_mut().for_each(|item| {
-            if Self::is_variable(&item) {
-                todo!("with")
-            }
-        });
-
-        for argument in self.arguments.values_mut() {
-            if let Some(inner_variable) = Self::get_inner(&argument) {
-                let replace_with = match inner_variable {
-                    Variable::Env(env_key) => std::env::var(env_key).with_context(|| {
-                        format!(
-                            "Could not find an environment variable with the name: '{:?}'",
-                            env_key
-                        )
-                    })?,
-                };
-
-                *argument = replace_with;
-            }
-        }
-
-        Ok(())
+impl Step {
+    /// Name of the step for errors, falls back to `uses` if no name is set.
+    pub fn display_name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.uses)
     }
 }
 
 #[serde_as]
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct HealthCheck {
+    /// Cron expression with seconds, e.g. `0 */5 * * * * *`.
     #[serde_as(as = "DisplayFromStr")]
+    #[schemars(with = "String")]
     pub period: Schedule, // TODO the struct `Schedule` is really large, maybe box or rc/arc it?
+    /// Path under which the server exposes the outcome of the last health check.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
     pub steps: Vec<Step>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Route {
+    /// Path of the route, e.g. `/deploy/{project}`.
     pub path: String,
+    /// Steps that validate the request before `steps` run, e.g. a signature check.
     pub pipeline: Vec<Step>,
     pub steps: Vec<Step>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct ConfigFile {
     pub version: ConfigVersion,
     pub config: Config,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub health_check: Option<HealthCheck>,
-    pub route: Route,
+    pub routes: Vec<Route>,
 }
 
 impl ConfigFile {
-    pub fn parse(path: impl AsRef<Path>) -> Result<ConfigFile> {
-        let config_file = std::fs::File::open(path)?;
-        // let config_reader = BufReader::new(config_file);
-
-        let config = serde_yaml::from_reader(config_file)?;
+    /// Parses a config file of the latest version, see [`crate::migrate::migrate`] for older
+    /// ones.
+    pub fn parse(source: &str) -> Result<ConfigFile> {
+        let config = serde_yaml::from_str(source)?;
 
         Ok(config)
     }
 
-    pub fn populate_env_variables(&mut self) -> Result<()> {
-        self.route
-            .pipeline
-            .iter_mut()
-            .for_each(|item| item.replace().unwrap());
-
-        self.route
-            .steps
-            .iter_mut()
-            .for_each(|item| item.replace().unwrap());
+    /// Reads the config file at `path`, migrates it to the latest version and validates it,
+    /// `actions` are the names of the built-in actions that can be used in `uses`.
+    ///
+    /// Every deprecation of the migration is logged as a warning.
+    pub fn load(path: impl AsRef<Path>, actions: &[&str]) -> Result<ConfigFile> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read the config file '{}'", path.display()))?;
+
+        let migrated = migrate(&source)
+            .with_context(|| format!("Could not migrate the config file '{}'", path.display()))?;
+        for deprecation in &migrated.deprecations {
+            warn!(
+                "{}:{}:{}: {}",
+                path.display(),
+                deprecation.line,
+                deprecation.column,
+                deprecation.message
+            );
+        }
 
-        Ok(())
+        validate_file(path, &migrated.source, actions)?;
+        ConfigFile::parse(&migrated.source)
+            .with_context(|| format!("Could not parse the config file '{}'", path.display()))
     }
 }
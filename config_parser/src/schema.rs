@@ -0,0 +1,173 @@
+//! JSON Schema of the config file, e.g. for the autocompletion and validation of
+//! yaml-language-server.
+//!
+//! The schema is derived from [`ConfigFile`], the `with` of the built-in actions is added for
+//! every step whose `uses` is the name of the action.
+
+use schemars::gen::SchemaSettings;
+use serde_json::{json, Map, Value};
+
+use crate::raw::ConfigFile;
+
+/// A built-in action, which is described in the schema.
+#[derive(Debug, Clone, Copy)]
+pub struct Action {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub with: &'static [Key],
+}
+
+/// A key of the `with` of an action, any value is allowed as it can be an expression.
+#[derive(Debug, Clone, Copy)]
+pub struct Key {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub required: bool,
+}
+
+impl Key {
+    pub const fn required(name: &'static str, description: &'static str) -> Key {
+        Key {
+            name,
+            description,
+            required: true,
+        }
+    }
+
+    pub const fn optional(name: &'static str, description: &'static str) -> Key {
+        Key {
+            name,
+            description,
+            required: false,
+        }
+    }
+}
+
+impl Action {
+    /// Only applies to steps that use the action, steps with a wasm plugin are skipped as they
+    /// can have any `uses`.
+    fn conditional_schema(&self) -> Value {
+        let properties = self
+            .with
+            .iter()
+            .map(|key| {
+                (
+                    key.name.to_string(),
+                    json!({ "description": key.description }),
+                )
+            })
+            .collect::<Map<_, _>>();
+        let required = self
+            .with
+            .iter()
+            .filter(|key| key.required)
+            .map(|key| key.name)
+            .collect::<Vec<_>>();
+
+        json!({
+            "if": {
+                "properties": {
+                    "uses": { "const": self.name },
+                    "with": { "not": { "required": ["wasm"] } }
+                },
+                "required": ["uses"]
+            },
+            "then": {
+                "properties": {
+                    "with": {
+                        "type": "object",
+                        "properties": properties,
+                        "required": required,
+                        "additionalProperties": false
+                    }
+                },
+                "required": if required.is_empty() { vec![] } else { vec!["with"] }
+            }
+        })
+    }
+}
+
+/// The JSON Schema of the config file with the `with` keys of `actions`.
+pub fn config_schema(actions: &[Action]) -> Value {
+    let schema = SchemaSettings::draft07()
+        .into_generator()
+        .into_root_schema_for::<ConfigFile>();
+    let mut schema = serde_json::to_value(schema).expect("The schema is valid JSON");
+
+    let step = &mut schema["definitions"]["Step"];
+    step["allOf"] = actions
+        .iter()
+        .map(Action::conditional_schema)
+        .collect::<Vec<_>>()
+        .into();
+    step["properties"]["uses"]["examples"] = actions
+        .iter()
+        .map(|action| action.name)
+        .collect::<Vec<_>>()
+        .into();
+    step["properties"]["uses"]["description"] = actions
+        .iter()
+        .map(|action| format!("`{}`: {}", action.name, action.description))
+        .fold(
+            "The built-in action or the name of the wasm plugin in `with.wasm`:".to_string(),
+            |description, action| description + "\n- " + &action,
+        )
+        .into();
+
+    schema
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_the_with_of_actions() {
+        const SHELL: Action = Action {
+            name: "shell/run",
+            description: "Runs a command",
+            with: &[
+                Key::required("run", "The command"),
+                Key::optional("shell", "The shell"),
+            ],
+        };
+        let schema = config_schema(&[SHELL]);
+
+        assert_eq!(
+            schema["properties"]["version"]["$ref"],
+            "#/definitions/ConfigVersion"
+        );
+        assert_eq!(
+            schema["definitions"]["ConfigVersion"]["type"],
+            json!(["string", "number"])
+        );
+        assert_eq!(
+            schema["definitions"]["ConfigVersion"]["enum"],
+            json!(["1.0", 1.0])
+        );
+        // what yaml-language-server validates for an unquoted `version: 1.0`
+        assert_eq!(
+            serde_yaml::from_str::<Value>("1.0").unwrap(),
+            schema["definitions"]["ConfigVersion"]["enum"][1]
+        );
+        assert_eq!(schema["definitions"]["Step"]["additionalProperties"], false);
+        assert_eq!(
+            schema["definitions"]["Step"]["properties"]["uses"]["examples"],
+            json!(["shell/run"])
+        );
+
+        let conditional = &schema["definitions"]["Step"]["allOf"][0];
+        assert_eq!(
+            conditional["if"]["properties"]["uses"]["const"],
+            "shell/run"
+        );
+        assert_eq!(
+            conditional["then"]["properties"]["with"]["required"],
+            json!(["run"])
+        );
+        assert_eq!(
+            conditional["then"]["properties"]["with"]["properties"]["shell"]["description"],
+            "The shell"
+        );
+    }
+}
@@ -0,0 +1,119 @@
+use std::fmt::{Debug, Display, Formatter};
+
+use serde::{Serialize, Serializer};
+
+const REDACTED: &str = "***";
+
+/// A value that is printed as `***` by `Debug`, `Display` and `Serialize`, so that it doesn't
+/// end up in logs or errors. [`Secret::reveal`] is only called when the value is handed to a
+/// plugin or an action.
+#[derive(Clone, PartialEq, Eq, Default)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Secret<T> {
+        Secret(value)
+    }
+
+    pub fn reveal(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> Debug for Secret<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl<T> Display for Secret<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl<T> Serialize for Secret<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(REDACTED)
+    }
+}
+
+/// Replaces the revealed values of secrets with `***` in text that is logged or returned, e.g.
+/// an error of an action that contains a URL with a token.
+#[derive(Debug, Clone, Default)]
+pub struct Redactor {
+    /// Longest first, so that a secret that contains another one is replaced as a whole.
+    secrets: Vec<String>,
+}
+
+impl Redactor {
+    pub fn new(secrets: impl IntoIterator<Item = String>) -> Redactor {
+        let mut secrets = secrets
+            .into_iter()
+            .filter(|secret| !secret.is_empty())
+            .collect::<Vec<_>>();
+        secrets.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        secrets.dedup();
+
+        Redactor { secrets }
+    }
+
+    pub fn redact(&self, text: &str) -> String {
+        self.secrets.iter().fold(text.to_string(), |text, secret| {
+            text.replace(secret, REDACTED)
+        })
+    }
+
+    /// Redacts the message of `err` and of each of its causes.
+    pub fn redact_error(&self, err: anyhow::Error) -> anyhow::Error {
+        if self.secrets.is_empty() {
+            return err;
+        }
+
+        let mut messages = err
+            .chain()
+            .map(|cause| self.redact(&cause.to_string()))
+            .collect::<Vec<_>>();
+        let mut redacted = anyhow::Error::msg(messages.pop().unwrap_or_default());
+        while let Some(message) = messages.pop() {
+            redacted = redacted.context(message);
+        }
+
+        redacted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_redacted() {
+        let secret = Secret::new("hunter2".to_string());
+
+        assert_eq!(format!("{:?} {}", secret, secret), "*** ***");
+        assert_eq!(serde_json::to_string(&secret).unwrap(), r#""***""#);
+        assert_eq!(secret.reveal(), "hunter2");
+    }
+
+    #[test]
+    fn redacts_text_and_errors() {
+        let redactor = Redactor::new([
+            "token".to_string(),
+            "https://example.com/?token=token".to_string(),
+            String::new(),
+        ]);
+
+        assert_eq!(
+            redactor.redact("GET https://example.com/?token=token and token"),
+            "GET *** and ***"
+        );
+
+        let err = anyhow::anyhow!("invalid token")
+            .context("The request to 'https://example.com/?token=token' failed");
+        assert_eq!(
+            format!("{:#}", redactor.redact_error(err)),
+            "The request to '***' failed: invalid ***"
+        );
+    }
+}
@@ -0,0 +1,754 @@
+//! Validation of a config file before it's loaded.
+//!
+//! serde stops at the first error and drops unknown keys silently, so the file is parsed into
+//! a tree that keeps the location of every node first. All problems of the tree are collected
+//! and reported at once, e.g. unknown keys, invalid expressions, unknown actions, values of the
+//! request in commands and wasm plugins that don't exist or don't export the required functions.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::Result;
+use cron::Schedule;
+use schemars::gen::SchemaSettings;
+use schemars::JsonSchema;
+
+use crate::expression::{Template, RUNTIME_ROOTS};
+use crate::raw::{Config, ConfigFile, ConfigVersion, HealthCheck, Route, Step};
+use crate::vars::Resolvers;
+use crate::yaml::{self, Kind, Node};
+
+/// The keys of the `with` of actions that start a process. An expression that is only known
+/// while the steps run, e.g. a header of the request, could inject commands or change how the
+/// process runs, e.g. with `LD_PRELOAD` in its environment.
+const COMMANDS: [(&str, &[&str]); 1] =
+    [("shell/run", &["run", "shell", "working_directory", "env"])];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+/// All problems of a config file, one per line in the form `file:line:column: message`.
+#[derive(Debug)]
+pub struct ValidationError {
+    pub file: PathBuf,
+    pub problems: Vec<Problem>,
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The config file '{}' has {} problem(s):",
+            self.file.display(),
+            self.problems.len()
+        )?;
+
+        for problem in &self.problems {
+            write!(
+                f,
+                "\n  {}:{}:{}: {}",
+                self.file.display(),
+                problem.line,
+                problem.column,
+                problem.message
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// The required and the optional keys of the struct `T` of the config file. They are taken from
+/// its JSON Schema, so they are the keys that serde accepts.
+fn keys<T: JsonSchema>() -> (Vec<String>, Vec<String>) {
+    let schema = SchemaSettings::draft07()
+        .into_generator()
+        .into_root_schema_for::<T>();
+    let Some(object) = schema.schema.object else {
+        return (Vec::new(), Vec::new());
+    };
+
+    let required = object.required.iter().cloned().collect::<Vec<_>>();
+    let optional = object
+        .properties
+        .keys()
+        .filter(|key| !object.required.contains(*key))
+        .cloned()
+        .collect();
+
+    (required, optional)
+}
+
+struct Validator<'a> {
+    actions: &'a [&'a str],
+    problems: Vec<Problem>,
+}
+
+impl<'a> Validator<'a> {
+    fn problem(&mut self, node: &Node, message: impl Into<String>) {
+        self.problems.push(Problem {
+            line: node.line,
+            column: node.column,
+            message: message.into(),
+        });
+    }
+
+    /// Checks that `node` is a map with all required keys of the struct `T` and no keys besides
+    /// the ones of `T`.
+    fn map<'n, T: JsonSchema>(&mut self, node: &'n Node, name: &str) -> HashMap<&'n str, &'n Node> {
+        let (required, optional) = keys::<T>();
+        let Kind::Map(entries) = &node.kind else {
+            self.problem(node, format!("`{}` must be a map", name));
+            return HashMap::new();
+        };
+
+        let mut found = HashMap::new();
+        for (key, value) in entries {
+            match key.as_str() {
+                Some(key_name) if required.iter().chain(&optional).any(|key| key == key_name) => {
+                    if found.insert(key_name, value).is_some() {
+                        self.problem(key, format!("Duplicate key '{}' in `{}`", key_name, name));
+                    }
+                }
+                Some(key_name) => self.problem(
+                    key,
+                    format!(
+                        "Unknown key '{}' in `{}`, expected one of: {}",
+                        key_name,
+                        name,
+                        required
+                            .iter()
+                            .chain(&optional)
+                            .map(String::as_str)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                ),
+                None => self.problem(key, format!("The keys of `{}` must be strings", name)),
+            }
+        }
+
+        // the start mark of a block map is behind its first key, so the first key is reported
+        let location = entries.first().map_or(node, |(key, _)| key);
+        for key in &required {
+            if !found.contains_key(key.as_str()) {
+                self.problem(location, format!("`{}` is missing the key '{}'", name, key));
+            }
+        }
+
+        found
+    }
+
+    fn list<'n>(&mut self, node: &'n Node, name: &str) -> &'n [Node] {
+        match &node.kind {
+            Kind::List(items) => items,
+            _ => {
+                self.problem(node, format!("`{}` must be a list", name));
+                &[]
+            }
+        }
+    }
+
+    fn scalar<'n>(&mut self, node: &'n Node, name: &str) -> Option<&'n str> {
+        let scalar = node.as_str();
+        if scalar.is_none() {
+            self.problem(node, format!("`{}` must be a string", name));
+        }
+
+        scalar
+    }
+
+    fn parse<T>(&mut self, node: &Node, name: &str)
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        if let Some(value) = self.scalar(node, name) {
+            if let Err(err) = value.parse::<T>() {
+                self.problem(node, format!("Invalid `{}` '{}': {}", name, value, err));
+            }
+        }
+    }
+
+    /// Checks the expressions of every string in `node`.
+    fn expressions(&mut self, node: &Node) {
+        match &node.kind {
+            Kind::Scalar { value, .. } => {
+                if let Err(err) = Template::parse(value) {
+                    self.problem(
+                        node,
+                        format!(
+                            "Invalid expression '{}' ({} at character {})",
+                            value, err.message, err.column
+                        ),
+                    );
+                }
+            }
+            Kind::List(items) => items.iter().for_each(|item| self.expressions(item)),
+            Kind::Map(entries) => entries
+                .iter()
+                .for_each(|(_, value)| self.expressions(value)),
+            Kind::Alias => {}
+        }
+    }
+
+    fn config_file(&mut self, node: &Node) {
+        let file = self.map::<ConfigFile>(node, "config file");
+
+        if let Some(version) = file.get("version") {
+            self.parse::<ConfigVersion>(version, "version");
+        }
+
+        if let Some(config) = file.get("config") {
+            let config = self.map::<Config>(config, "config");
+            if let Some(bind) = config.get("bind") {
+                self.parse::<SocketAddr>(bind, "config.bind");
+            }
+            for key in ["uri", "cache_dir"] {
+                if let Some(value) = config.get(key) {
+                    self.scalar(value, &format!("config.{}", key));
+                }
+            }
+        }
+
+        if let Some(health_check) = file.get("health_check").filter(|node| !node.is_null()) {
+            let health_check = self.map::<HealthCheck>(health_check, "health_check");
+            if let Some(period) = health_check.get("period") {
+                self.parse::<Schedule>(period, "health_check.period");
+            }
+            if let Some(path) = health_check.get("path") {
+                self.scalar(path, "health_check.path");
+            }
+            if let Some(steps) = health_check.get("steps") {
+                self.steps(steps, "health_check.steps");
+            }
+        }
+
+        if let Some(routes) = file.get("routes") {
+            for (index, route) in self.list(routes, "routes").iter().enumerate() {
+                let name = format!("routes[{}]", index);
+                let route = self.map::<Route>(route, &name);
+
+                if let Some(path) = route.get("path") {
+                    self.scalar(path, &format!("{}.path", name));
+                }
+                for key in ["pipeline", "steps"] {
+                    if let Some(steps) = route.get(key) {
+                        self.steps(steps, &format!("{}.{}", name, key));
+                    }
+                }
+            }
+        }
+    }
+
+    fn steps(&mut self, node: &Node, name: &str) {
+        for (index, step) in self.list(node, name).iter().enumerate() {
+            self.step(step, &format!("{}[{}]", name, index));
+        }
+    }
+
+    fn step(&mut self, node: &Node, name: &str) {
+        let step = self.map::<Step>(node, name);
+
+        if let Some(id) = step.get("id") {
+            self.scalar(id, &format!("{}.id", name));
+        }
+        if let Some(step_name) = step.get("name") {
+            self.scalar(step_name, &format!("{}.name", name));
+            self.expressions(step_name);
+            self.logged(step_name, &format!("{}.name", name));
+        }
+        if let Some(uses) = step.get("uses") {
+            self.logged(uses, &format!("{}.uses", name));
+        }
+
+        let mut with = HashMap::new();
+        for key in ["with", "arguments"] {
+            if let Some(map) = step.get(key) {
+                let map = self.map_of_values(map, &format!("{}.{}", name, key));
+                if key == "with" {
+                    with = map;
+                }
+            }
+        }
+
+        match with.get("wasm") {
+            Some(wasm) => self.wasm(wasm, &format!("{}.with.wasm", name)),
+            None => {
+                if let Some(uses) = step.get("uses") {
+                    self.uses(uses, &format!("{}.uses", name));
+                    self.commands(uses, &with, name);
+                }
+            }
+        }
+    }
+
+    /// The values of the action `uses` that reach a process must not contain values that are only
+    /// known while the steps run, see [`COMMANDS`].
+    fn commands(&mut self, uses: &Node, with: &HashMap<&str, &Node>, name: &str) {
+        for (action, keys) in COMMANDS {
+            if uses.as_str() != Some(action) {
+                continue;
+            }
+
+            for key in keys {
+                if let Some(node) = with.get(key) {
+                    self.runtime_values(node, &format!("{}.with.{}", name, key));
+                }
+            }
+        }
+    }
+
+    /// Reports every expression in `node` that is only known while the steps run.
+    fn runtime_values(&mut self, node: &Node, name: &str) {
+        match &node.kind {
+            Kind::Scalar { value, .. } => {
+                let Ok(template) = Template::parse(value) else {
+                    return;
+                };
+
+                for path in template.paths() {
+                    if RUNTIME_ROOTS.contains(&path[0].as_str()) {
+                        self.problem(
+                            node,
+                            format!(
+                                "`{}` must not contain `{}`, it could inject commands",
+                                name,
+                                path.join(".")
+                            ),
+                        );
+                    }
+                }
+            }
+            Kind::List(items) => items
+                .iter()
+                .for_each(|item| self.runtime_values(item, name)),
+            Kind::Map(entries) => entries
+                .iter()
+                .for_each(|(_, value)| self.runtime_values(value, name)),
+            Kind::Alias => {}
+        }
+    }
+
+    /// `node` is logged as is, so it must not contain secrets, see [`Resolvers::secret_path`].
+    fn logged(&mut self, node: &Node, name: &str) {
+        if let Some(path) = node
+            .as_str()
+            .and_then(|value| Resolvers::global().secret_path(value))
+        {
+            self.problem(
+                node,
+                format!(
+                    "`{}` must not contain the secret `{}`, it is logged",
+                    name, path
+                ),
+            );
+        }
+    }
+
+    /// `with` and `arguments` are maps with any values.
+    fn map_of_values<'n>(&mut self, node: &'n Node, name: &str) -> HashMap<&'n str, &'n Node> {
+        let Kind::Map(entries) = &node.kind else {
+            self.problem(node, format!("`{}` must be a map", name));
+            return HashMap::new();
+        };
+
+        let mut found = HashMap::new();
+        for (key, value) in entries {
+            match key.as_str() {
+                Some(key_name) => {
+                    found.insert(key_name, value);
+                }
+                None => self.problem(key, format!("The keys of `{}` must be strings", name)),
+            }
+            self.expressions(value);
+        }
+
+        found
+    }
+
+    fn uses(&mut self, node: &Node, name: &str) {
+        let Some(uses) = self.scalar(node, name) else {
+            return;
+        };
+
+        match Template::parse(uses) {
+            Ok(template) if template.is_literal() => {
+                if !self.actions.contains(&uses) {
+                    self.problem(
+                        node,
+                        format!(
+                            "Unknown action '{}', expected one of: {} or a wasm plugin in `with.wasm`",
+                            uses,
+                            self.actions.join(", ")
+                        ),
+                    );
+                }
+            }
+            // the action decides what the step does, so it has to be known without running it
+            Ok(_) => self.problem(
+                node,
+                format!(
+                    "`{}` must be the name of an action, expressions are only allowed for a wasm plugin in `with.wasm`",
+                    name
+                ),
+            ),
+            Err(_) => self.expressions(node),
+        }
+    }
+
+    fn wasm(&mut self, node: &Node, name: &str) {
+        let Some(path) = self.scalar(node, name) else {
+            return;
+        };
+
+        let path = match Resolvers::global().render(path) {
+            Ok(Some(rendered)) => rendered,
+            Ok(None) => path.to_string(),
+            Err(err) => {
+                self.problem(
+                    node,
+                    format!("Could not resolve the path of the wasm plugin: {:#}", err),
+                );
+                return;
+            }
+        };
+
+        let bytes = match std::fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                self.problem(
+                    node,
+                    format!("Could not read the wasm plugin '{}': {}", path, err),
+                );
+                return;
+            }
+        };
+
+        match glue::plugin::missing_exports(&bytes) {
+            Ok(missing) if missing.is_empty() => {}
+            Ok(missing) => self.problem(
+                node,
+                format!(
+                    "The wasm plugin '{}' doesn't export: {}",
+                    path,
+                    missing.join(", ")
+                ),
+            ),
+            Err(err) => self.problem(
+                node,
+                format!("'{}' is not a valid wasm plugin: {:#}", path, err),
+            ),
+        }
+    }
+}
+
+/// Validates the content of a config file, `actions` are the names of the built-in actions
+/// that can be used in `uses`.
+pub fn validate(source: &str, actions: &[&str]) -> Vec<Problem> {
+    let root = match yaml::parse(source) {
+        Ok(root) => root,
+        Err(err) => {
+            return vec![Problem {
+                line: err.marker().line(),
+                column: err.marker().col() + 1,
+                message: err.info().to_string(),
+            }]
+        }
+    };
+
+    let mut validator = Validator {
+        actions,
+        problems: Vec::new(),
+    };
+    match &root {
+        Some(root) => validator.config_file(root),
+        None => validator.problems.push(Problem {
+            line: 1,
+            column: 1,
+            message: "The config file is empty".to_string(),
+        }),
+    }
+
+    validator.problems
+}
+
+/// Validates `source`, the content of the config file at `path`, all problems are returned as
+/// one [`ValidationError`].
+pub fn validate_file(path: impl AsRef<Path>, source: &str, actions: &[&str]) -> Result<()> {
+    let path = path.as_ref();
+    let problems = validate(source, actions);
+    if !problems.is_empty() {
+        return Err(ValidationError {
+            file: path.to_path_buf(),
+            problems,
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ACTIONS: [&str; 2] = ["docker/ping", "shell/run"];
+
+    fn problems(source: &str) -> Vec<(usize, usize, String)> {
+        validate(source, &ACTIONS)
+            .into_iter()
+            .map(|problem| (problem.line, problem.column, problem.message))
+            .collect()
+    }
+
+    #[test]
+    fn accepts_valid_config() {
+        let source = r#"
+version: 1.0
+config:
+  bind: 0.0.0.0:3000
+  uri: https://example.com
+health_check:
+  period: "0 5 * * * * *"
+  steps:
+    - uses: docker/ping
+routes:
+  - path: /deploy
+    pipeline: []
+    steps:
+      - uses: shell/run
+        with:
+          run: echo "$EVENT:$TAG"
+          env: { EVENT: "${{ env.EVENT || 'none' }}", TAG: latest }
+          ports: [8080:80]
+"#;
+
+        assert_eq!(problems(source), []);
+    }
+
+    #[test]
+    fn reports_all_problems_with_location() {
+        let source = r#"
+version: 2.0
+config:
+  bind: 0.0.0.0:3000
+  url: https://example.com
+health_check:
+  period: every minute
+  steps:
+    - uses: docker/pong
+routes:
+  - path: /deploy
+    steps:
+      - uses: shell/run
+        nmae: typo
+        with:
+          run: ${{ env.TAG
+      - uses: http_validator_wasm
+        with:
+          wasm: ./does/not/exist.wasm
+"#;
+
+        assert_eq!(
+            problems(source),
+            [
+                (2, 10, "Invalid `version` '2.0': Unknown version: 2.0".to_string()),
+                (
+                    5,
+                    3,
+                    "Unknown key 'url' in `config`, expected one of: bind, cache_dir, uri"
+                        .to_string()
+                ),
+                (
+                    7,
+                    11,
+                    "Invalid `health_check.period` 'every minute': Invalid expression: Invalid cron expression."
+                        .to_string()
+                ),
+                (
+                    9,
+                    13,
+                    "Unknown action 'docker/pong', expected one of: docker/ping, shell/run or a wasm plugin in `with.wasm`"
+                        .to_string()
+                ),
+                (11, 5, "`routes[0]` is missing the key 'pipeline'".to_string()),
+                (
+                    14,
+                    9,
+                    "Unknown key 'nmae' in `routes[0].steps[0]`, expected one of: uses, arguments, id, name, with"
+                        .to_string()
+                ),
+                (
+                    16,
+                    16,
+                    "Invalid expression '${{ env.TAG' (Expected '}}' at character 12)".to_string()
+                ),
+                (
+                    19,
+                    17,
+                    "Could not read the wasm plugin './does/not/exist.wasm': No such file or directory (os error 2)"
+                        .to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn takes_keys_from_the_structs() {
+        assert_eq!(
+            keys::<Step>(),
+            (
+                vec!["uses".to_string()],
+                ["arguments", "id", "name", "with"]
+                    .map(String::from)
+                    .to_vec()
+            )
+        );
+        // `bind` has a default
+        assert_eq!(
+            keys::<Config>(),
+            (
+                Vec::new(),
+                ["bind", "cache_dir", "uri"].map(String::from).to_vec()
+            )
+        );
+    }
+
+    #[test]
+    fn rejects_runtime_values_in_commands() {
+        let source = r#"
+version: 1.0
+config:
+  bind: 0.0.0.0:3000
+routes:
+  - path: /deploy/{project}
+    pipeline: []
+    steps:
+      - uses: shell/run
+        with:
+          run: "git checkout ${{ request.body.json.ref }} && ./deploy ${{ env.TARGET }}"
+          shell: ${{ request.headers.x-shell }}
+          working_directory: /srv/${{ route.params.project }}
+          env:
+            REF: ${{ steps.build.outputs.ref }}
+      - uses: shell/run
+        with:
+          run: ./deploy
+          env: "REF=${{ request.body.json.ref }}"
+      - uses: ${{ request.headers.x-action }}
+"#;
+
+        let message = |step: usize, key: &str, path: &str| {
+            format!(
+                "`routes[0].steps[{}].with.{}` must not contain `{}`, it could inject commands",
+                step, key, path
+            )
+        };
+        let mut problems = problems(source);
+        problems.sort();
+        assert_eq!(
+            problems,
+            [
+                (11, 16, message(0, "run", "request.body.json.ref")),
+                (12, 18, message(0, "shell", "request.headers.x-shell")),
+                (
+                    13,
+                    30,
+                    message(0, "working_directory", "route.params.project")
+                ),
+                (15, 18, message(0, "env", "steps.build.outputs.ref")),
+                (19, 16, message(1, "env", "request.body.json.ref")),
+                (
+                    20,
+                    15,
+                    "`routes[0].steps[2].uses` must be the name of an action, expressions are only allowed for a wasm plugin in `with.wasm`"
+                        .to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_secrets_in_logged_values() {
+        let source = r#"
+version: 1.0
+config:
+  bind: 0.0.0.0:3000
+routes:
+  - path: /deploy
+    pipeline: []
+    steps:
+      - uses: ${{ env.ACTION }}
+        name: Deploy ${{ credentials.tag }}
+"#;
+
+        assert_eq!(
+            problems(source),
+            [
+                (
+                    10,
+                    15,
+                    "`routes[0].steps[0].name` must not contain the secret `credentials.tag`, it is logged"
+                        .to_string()
+                ),
+                (
+                    9,
+                    15,
+                    "`routes[0].steps[0].uses` must not contain the secret `env.ACTION`, it is logged"
+                        .to_string()
+                ),
+                (
+                    9,
+                    15,
+                    "`routes[0].steps[0].uses` must be the name of an action, expressions are only allowed for a wasm plugin in `with.wasm`"
+                        .to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_exports_of_plugins() {
+        let path = std::env::temp_dir().join(format!("validate_{}.wat", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"(module (func (export "_setup") (result i32) (i32.const 0)))"#,
+        )
+        .unwrap();
+
+        let source = format!(
+            "version: 1.0\nconfig:\n  bind: 0.0.0.0:3000\nroutes:\n  - path: /\n    pipeline:\n      - uses: validator\n        with:\n          wasm: {}\n    steps: []\n",
+            path.display()
+        );
+        let problems = problems(&source);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(problems.len(), 1);
+        assert_eq!((problems[0].0, problems[0].1), (9, 17));
+        assert!(problems[0]
+            .2
+            .ends_with("doesn't export: memory, alloc, dealloc, get_err_no, get_err_msg, err_clear, get_response_ptr, get_response_len, http_validator"));
+    }
+
+    #[test]
+    fn reports_syntax_errors() {
+        assert_eq!(
+            problems("version: 1.0\nconfig: [\n"),
+            [(
+                3,
+                1,
+                "while parsing a node, did not find expected node content".to_string()
+            )]
+        );
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde::de::{self, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::expression::check_template;
+use crate::secret::Secret;
+
+/// A value of `with` or `arguments`, any YAML value is allowed, e.g. `ports: [8080:80]` or
+/// `auto_remove: true`.
+///
+/// The expressions of every string are checked while deserializing, a string becomes a
+/// [`Secret`] once its variables are resolved when the config is loaded, e.g.
+/// `${{ env.GITHUB_TOKEN }}`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum StepValue {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Secret(Secret<String>),
+    List(Vec<StepValue>),
+    Map(BTreeMap<String, StepValue>),
+}
+
+impl StepValue {
+    /// The value if it's a string or a secret.
+    pub fn reveal_str(&self) -> Option<&str> {
+        match self {
+            StepValue::String(value) => Some(value),
+            StepValue::Secret(secret) => Some(secret.reveal()),
+            _ => None,
+        }
+    }
+
+    /// The revealed values of all secrets in the value, e.g. for a [`Redactor`].
+    ///
+    /// [`Redactor`]: crate::secret::Redactor
+    pub fn revealed_secrets(&self) -> Vec<&str> {
+        match self {
+            StepValue::Secret(secret) => vec![secret.reveal()],
+            StepValue::List(items) => items.iter().flat_map(StepValue::revealed_secrets).collect(),
+            StepValue::Map(entries) => entries
+                .values()
+                .flat_map(StepValue::revealed_secrets)
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The value as a string if it's a bool, a number, a string or a secret.
+    pub fn reveal_scalar(&self) -> Option<String> {
+        match self {
+            StepValue::Bool(value) => Some(value.to_string()),
+            StepValue::Integer(value) => Some(value.to_string()),
+            StepValue::Float(value) => Some(value.to_string()),
+            StepValue::String(value) => Some(value.clone()),
+            StepValue::Secret(secret) => Some(secret.reveal().clone()),
+            StepValue::Null | StepValue::List(_) | StepValue::Map(_) => None,
+        }
+    }
+}
+
+impl Display for StepValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StepValue::Null => Ok(()),
+            StepValue::Bool(value) => write!(f, "{}", value),
+            StepValue::Integer(value) => write!(f, "{}", value),
+            StepValue::Float(value) => write!(f, "{}", value),
+            StepValue::String(value) => write!(f, "{}", value),
+            StepValue::Secret(secret) => write!(f, "{}", secret),
+            StepValue::List(items) => {
+                write!(f, "[")?;
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            StepValue::Map(entries) => {
+                write!(f, "{{")?;
+                for (index, (key, value)) in entries.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", key, value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+impl JsonSchema for StepValue {
+    fn schema_name() -> String {
+        "StepValue".to_string()
+    }
+
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        Schema::Bool(true)
+    }
+}
+
+impl From<&str> for StepValue {
+    fn from(value: &str) -> Self {
+        StepValue::String(value.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for StepValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct StepValueVisitor;
+
+        impl<'de> Visitor<'de> for StepValueVisitor {
+            type Value = StepValue;
+
+            fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+                formatter.write_str("any value")
+            }
+
+            fn visit_bool<E: de::Error>(self, value: bool) -> Result<Self::Value, E> {
+                Ok(StepValue::Bool(value))
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> Result<Self::Value, E> {
+                Ok(StepValue::Integer(value))
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
+                Ok(i64::try_from(value)
+                    .map(StepValue::Integer)
+                    .unwrap_or(StepValue::Float(value as f64)))
+            }
+
+            fn visit_f64<E: de::Error>(self, value: f64) -> Result<Self::Value, E> {
+                Ok(StepValue::Float(value))
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                check_template(value)?;
+
+                Ok(StepValue::String(value.to_string()))
+            }
+
+            fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+                Ok(StepValue::Null)
+            }
+
+            fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+                Ok(StepValue::Null)
+            }
+
+            fn visit_some<D: Deserializer<'de>>(
+                self,
+                deserializer: D,
+            ) -> Result<Self::Value, D::Error> {
+                StepValue::deserialize(deserializer)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut items = Vec::new();
+                while let Some(item) = seq.next_element()? {
+                    items.push(item);
+                }
+
+                Ok(StepValue::List(items))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut entries = BTreeMap::new();
+                while let Some((key, value)) = map.next_entry()? {
+                    entries.insert(key, value);
+                }
+
+                Ok(StepValue::Map(entries))
+            }
+        }
+
+        deserializer.deserialize_any(StepValueVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deserializes_any_value() {
+        let value: StepValue = serde_yaml::from_str(
+            "ports: [8080:80, 8443]\nauto_remove: true\nratio: 0.5\nempty:\nenv: { TAG: v1 }",
+        )
+        .unwrap();
+
+        assert_eq!(
+            value,
+            StepValue::Map(BTreeMap::from([
+                (
+                    "ports".to_string(),
+                    StepValue::List(vec!["8080:80".into(), StepValue::Integer(8443)])
+                ),
+                ("auto_remove".to_string(), StepValue::Bool(true)),
+                ("ratio".to_string(), StepValue::Float(0.5)),
+                ("empty".to_string(), StepValue::Null),
+                (
+                    "env".to_string(),
+                    StepValue::Map(BTreeMap::from([("TAG".to_string(), "v1".into())]))
+                ),
+            ]))
+        );
+        assert_eq!(
+            value.to_string(),
+            "{auto_remove: true, empty: , env: {TAG: v1}, ports: [8080:80, 8443], ratio: 0.5}"
+        );
+    }
+
+    #[test]
+    fn checks_nested_expressions() {
+        let err = serde_yaml::from_str::<StepValue>("hosts:\n  - ${{ env.HOST\n").unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "hosts[0]: Invalid expression '${{ env.HOST' (Expected '}}' at character 13) at line 2 column 5"
+        );
+    }
+}
@@ -0,0 +1,554 @@
+//! Resolution of the variables in the config, e.g. `${{ env.GITHUB_TOKEN }}`.
+//!
+//! Every source of variables is a [`Resolver`] for one root of a path. The variables of all
+//! sources in [`Resolvers::global`] are replaced when the config is loaded, the paths of the
+//! request (`request`, `route` and `steps`) are only known while a route runs and are
+//! evaluated by the executor, which falls back to [`Resolvers::global`] for everything else.
+
+use std::io::ErrorKind;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use anyhow::{bail, Context, Result};
+
+use crate::expression::{Template, Value};
+use crate::raw::{ConfigFile, Step};
+use crate::secret::Secret;
+use crate::value::StepValue;
+
+/// A source of variables, resolves every path that starts with [`Resolver::root`].
+pub trait Resolver: Send + Sync {
+    fn root(&self) -> &'static str;
+
+    /// Resolves `path` without the root, e.g. `["GITHUB_TOKEN"]` for `env.GITHUB_TOKEN`.
+    /// A variable that doesn't exist is `null`.
+    fn resolve(&self, path: &[String]) -> Result<Value>;
+
+    /// `true` if everything after `<root>.` up to the next whitespace, `)` or `}` is a single
+    /// segment, e.g. the path of a file.
+    fn takes_raw_path(&self) -> bool {
+        false
+    }
+
+    /// `true` if the values can contain secrets, e.g. a token. A value of `with` or `arguments`
+    /// that uses such a resolver is a [`Secret`].
+    fn is_secret(&self) -> bool {
+        true
+    }
+}
+
+/// Resolves `env.KEY` to the environment variable `KEY`.
+pub struct Env;
+
+impl Resolver for Env {
+    fn root(&self) -> &'static str {
+        "env"
+    }
+
+    fn resolve(&self, path: &[String]) -> Result<Value> {
+        match path {
+            [key] => Ok(std::env::var(key).map_or(Value::Null, Value::String)),
+            _ => bail!("Expected 'env.<name>'"),
+        }
+    }
+}
+
+/// Reads the content of a file, used for secrets that are mounted as files.
+/// A file that doesn't exist is `null`, a single trailing newline is removed.
+fn read_secret(path: &Path) -> Result<Value> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => {
+            let content = content.strip_suffix('\n').unwrap_or(&content);
+            let content = content.strip_suffix('\r').unwrap_or(content);
+
+            Ok(Value::String(content.to_string()))
+        }
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(Value::Null),
+        Err(err) => {
+            Err(err).with_context(|| format!("Could not read the file '{}'", path.display()))
+        }
+    }
+}
+
+/// Resolves `file./run/secrets/github` to the content of the file `/run/secrets/github`,
+/// e.g. for Docker secrets.
+pub struct File;
+
+impl Resolver for File {
+    fn root(&self) -> &'static str {
+        "file"
+    }
+
+    fn resolve(&self, path: &[String]) -> Result<Value> {
+        match path {
+            [path] => read_secret(Path::new(path)),
+            _ => bail!("Expected 'file.<path>'"),
+        }
+    }
+
+    fn takes_raw_path(&self) -> bool {
+        true
+    }
+}
+
+pub const CREDENTIALS_DIRECTORY: &str = "CREDENTIALS_DIRECTORY";
+
+/// Resolves `credentials.NAME` to the content of the file `NAME` in `$CREDENTIALS_DIRECTORY`,
+/// which systemd sets for the credentials of `LoadCredential=`.
+pub struct Credentials;
+
+impl Resolver for Credentials {
+    fn root(&self) -> &'static str {
+        "credentials"
+    }
+
+    fn resolve(&self, path: &[String]) -> Result<Value> {
+        let [name] = path else {
+            bail!("Expected 'credentials.<name>'");
+        };
+        if name.contains('/') {
+            bail!("The name of the credential '{}' must not contain '/'", name);
+        }
+
+        let directory = std::env::var_os(CREDENTIALS_DIRECTORY)
+            .with_context(|| format!("${} is not set", CREDENTIALS_DIRECTORY))?;
+
+        read_secret(&Path::new(&directory).join(name))
+    }
+
+    /// Credential names can contain dots, e.g. `github.token`.
+    fn takes_raw_path(&self) -> bool {
+        true
+    }
+}
+
+/// The sources of variables which are used to replace the variables of a config.
+pub struct Resolvers {
+    resolvers: Vec<Box<dyn Resolver>>,
+}
+
+impl Resolvers {
+    pub fn new() -> Resolvers {
+        Resolvers {
+            resolvers: Vec::new(),
+        }
+    }
+
+    /// The resolvers which are used to load the config and to run the steps.
+    pub fn global() -> &'static Resolvers {
+        static RESOLVERS: OnceLock<Resolvers> = OnceLock::new();
+
+        RESOLVERS.get_or_init(Resolvers::default)
+    }
+
+    /// Adds `resolver`, it replaces a resolver with the same root.
+    pub fn with(mut self, resolver: impl Resolver + 'static) -> Resolvers {
+        self.resolvers
+            .retain(|existing| existing.root() != resolver.root());
+        self.resolvers.push(Box::new(resolver));
+
+        self
+    }
+
+    pub fn takes_raw_path(&self, root: &str) -> bool {
+        self.resolvers
+            .iter()
+            .any(|resolver| resolver.root() == root && resolver.takes_raw_path())
+    }
+
+    pub fn is_secret(&self, root: &str) -> bool {
+        self.resolvers
+            .iter()
+            .any(|resolver| resolver.root() == root && resolver.is_secret())
+    }
+
+    /// The first path of a secret resolver in `value`, e.g. `env.GITHUB_TOKEN`.
+    pub fn secret_path(&self, value: &str) -> Option<String> {
+        Template::parse(value)
+            .ok()?
+            .paths()
+            .iter()
+            .find(|path| self.is_secret(&path[0]))
+            .map(|path| path.join("."))
+    }
+
+    pub fn roots(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.resolvers.iter().map(|resolver| resolver.root())
+    }
+
+    pub fn lookup(&self, path: &[String]) -> Result<Value> {
+        let (root, rest) = path.split_first().context("Empty variable")?;
+
+        match self
+            .resolvers
+            .iter()
+            .find(|resolver| resolver.root() == root)
+        {
+            Some(resolver) => resolver
+                .resolve(rest)
+                .with_context(|| format!("Could not resolve '{}'", path.join("."))),
+            None => bail!("Unknown variable '{}'", path.join(".")),
+        }
+    }
+
+    /// `true` if `value` contains expressions and all of them can be resolved by these resolvers,
+    /// other expressions, e.g. the ones that reference the request, are evaluated by the executor.
+    pub fn can_resolve(&self, value: &str) -> bool {
+        Template::parse(value)
+            .map(|template| {
+                !template.is_literal()
+                    && template
+                        .paths()
+                        .iter()
+                        .all(|path| self.roots().any(|root| root == path[0]))
+            })
+            .unwrap_or(false)
+    }
+
+    /// Renders `value` if [`Resolvers::can_resolve`] it.
+    pub fn render(&self, value: &str) -> Result<Option<String>> {
+        if !self.can_resolve(value) {
+            return Ok(None);
+        }
+
+        Template::parse(value)?
+            .render(&|path| self.lookup(path))
+            .map(Some)
+    }
+
+    /// Renders `value` if [`Resolvers::can_resolve`] it, otherwise it is kept as is.
+    pub fn replace(&self, value: &mut String) -> Result<()> {
+        if let Some(rendered) = self.render(value)? {
+            *value = rendered;
+        }
+
+        Ok(())
+    }
+
+    /// Like [`Resolvers::replace`], but fails if `value` contains a [`Resolvers::secret_path`].
+    /// Used for values that are logged, e.g. the name of a step.
+    pub fn replace_public(&self, value: &mut String) -> Result<()> {
+        if let Some(path) = self.secret_path(value) {
+            bail!(
+                "`{}` is a secret, it can't be used in a value that is logged",
+                path
+            );
+        }
+
+        self.replace(value)
+    }
+
+    /// Like [`Resolvers::replace`] for every string in `value`, but a string rendered with a
+    /// [`Resolver::is_secret`] is a [`Secret`] as it can contain e.g. a token.
+    pub fn replace_secret(&self, value: &mut StepValue) -> Result<()> {
+        match value {
+            StepValue::String(string) => {
+                if let Some(rendered) = self.render(string)? {
+                    *value = match self.secret_path(string) {
+                        Some(_) => StepValue::Secret(Secret::new(rendered)),
+                        None => StepValue::String(rendered),
+                    };
+                }
+            }
+            StepValue::List(items) => {
+                for item in items {
+                    self.replace_secret(item)?;
+                }
+            }
+            StepValue::Map(entries) => {
+                for (key, entry) in entries {
+                    self.replace_secret(entry)
+                        .with_context(|| format!("Could not replace the variables of '{}'", key))?;
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Resolvers {
+    fn default() -> Self {
+        Resolvers::new().with(Env).with(File).with(Credentials)
+    }
+}
+
+/// Replaces the variables which can be resolved when the config is loaded.
+pub trait ReplaceVariables {
+    fn replace_variables(&mut self, resolvers: &Resolvers) -> Result<()>;
+}
+
+impl ReplaceVariables for Step {
+    fn replace_variables(&mut self, resolvers: &Resolvers) -> Result<()> {
+        resolvers
+            .replace_public(&mut self.uses)
+            .context("Could not replace the variables of `uses`")?;
+
+        if let Some(name) = &mut self.name {
+            resolvers
+                .replace_public(name)
+                .context("Could not replace the variables of `name`")?;
+        }
+
+        for (key, item) in &mut self.with {
+            resolvers
+                .replace_secret(item)
+                .with_context(|| format!("Could not replace the variables of `with.{}`", key))?;
+        }
+
+        for (key, argument) in &mut self.arguments {
+            resolvers.replace_secret(argument).with_context(|| {
+                format!("Could not replace the variables of the argument '{}'", key)
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ReplaceVariables for ConfigFile {
+    /// Replaces the variables of the steps of the health check and of all routes.
+    fn replace_variables(&mut self, resolvers: &Resolvers) -> Result<()> {
+        if let Some(health_check) = &mut self.health_check {
+            for step in &mut health_check.steps {
+                step.replace_variables(resolvers).with_context(|| {
+                    format!(
+                        "Could not replace the variables of the step '{}' of the health check",
+                        step.display_name()
+                    )
+                })?;
+            }
+        }
+
+        for route in &mut self.routes {
+            for step in route.pipeline.iter_mut().chain(&mut route.steps) {
+                step.replace_variables(resolvers).with_context(|| {
+                    format!(
+                        "Could not replace the variables of the step '{}' of the route '{}'",
+                        step.display_name(),
+                        route.path
+                    )
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+version: 1.0
+config:
+  bind: 0.0.0.0:3000
+health_check:
+  period: "0 5 * * * * *"
+  steps:
+    - uses: http/request
+      with:
+        url: ${{ env.VARS_TEST_HEALTH_URL }}
+routes:
+  - path: /deploy
+    pipeline:
+      - uses: http_validator_wasm
+        arguments:
+          secret: ${{ env.VARS_TEST_SECRET }}
+    steps:
+      - uses: shell/run
+        name: Deploy ${{ 'latest' }}
+        with:
+          wasm: ${{ env.VARS_TEST_PLUGIN_DIR }}/x.wasm
+          url: ${{ request.headers.host }}
+          tag: ${{ 'latest' }}
+          hosts:
+            - ${{ env.VARS_TEST_HEALTH_URL }}
+            - static
+        arguments:
+          secret: ${{ env.VARS_TEST_SECRET }}
+"#;
+
+    #[test]
+    fn replaces_variables_in_every_field() {
+        std::env::set_var("VARS_TEST_PLUGIN_DIR", "/plugins");
+        std::env::set_var("VARS_TEST_SECRET", "hunter2");
+        std::env::set_var("VARS_TEST_HEALTH_URL", "http://localhost/health");
+
+        let mut config: ConfigFile = serde_yaml::from_str(CONFIG).unwrap();
+        config.replace_variables(Resolvers::global()).unwrap();
+
+        let health_check = config.health_check.as_ref().unwrap();
+        assert_eq!(
+            health_check.steps[0].with["url"].reveal_str(),
+            Some("http://localhost/health")
+        );
+        assert_eq!(
+            config.routes[0].pipeline[0].arguments["secret"].reveal_str(),
+            Some("hunter2")
+        );
+
+        let step = &config.routes[0].steps[0];
+        assert_eq!(step.uses, "shell/run");
+        assert_eq!(step.name.as_deref(), Some("Deploy latest"));
+        assert_eq!(step.with["wasm"].reveal_str(), Some("/plugins/x.wasm"));
+        // evaluated per request by the executor
+        assert_eq!(
+            step.with["url"],
+            StepValue::String("${{ request.headers.host }}".to_string())
+        );
+        assert_eq!(step.arguments["secret"].reveal_str(), Some("hunter2"));
+        // only values of secret resolvers are secrets
+        assert_eq!(step.with["tag"], StepValue::String("latest".to_string()));
+        assert_eq!(
+            step.with["hosts"],
+            StepValue::List(vec![
+                StepValue::Secret(Secret::new("http://localhost/health".to_string())),
+                "static".into()
+            ])
+        );
+        assert!(!format!("{:?}", config).contains("hunter2"));
+    }
+
+    #[test]
+    fn missing_variable_is_an_error() {
+        let mut config: ConfigFile =
+            serde_yaml::from_str(&CONFIG.replace("VARS_TEST_", "VARS_TEST_MISSING_")).unwrap();
+        let error = |config: &mut ConfigFile| {
+            format!(
+                "{:#}",
+                config.replace_variables(Resolvers::global()).unwrap_err()
+            )
+        };
+
+        assert_eq!(
+            error(&mut config),
+            "Could not replace the variables of the step 'http/request' of the health check: \
+             Could not replace the variables of `with.url`: \
+             The expression 'env.VARS_TEST_MISSING_HEALTH_URL' has no value"
+        );
+
+        config.health_check = None;
+        assert_eq!(
+            error(&mut config),
+            "Could not replace the variables of the step 'http_validator_wasm' of the route '/deploy': \
+             Could not replace the variables of the argument 'secret': \
+             The expression 'env.VARS_TEST_MISSING_SECRET' has no value"
+        );
+
+        config.routes[0].pipeline.clear();
+        config.routes[0].steps[0]
+            .with
+            .retain(|key, _| key == "wasm");
+        assert_eq!(
+            error(&mut config),
+            "Could not replace the variables of the step 'Deploy latest' \
+             of the route '/deploy': Could not replace the variables of `with.wasm`: \
+             The expression 'env.VARS_TEST_MISSING_PLUGIN_DIR' has no value"
+        );
+    }
+
+    #[test]
+    fn rejects_secrets_in_logged_values() {
+        let mut step: Step =
+            serde_yaml::from_str("uses: shell/run\nname: Deploy ${{ file./run/secrets/tag }}")
+                .unwrap();
+
+        assert_eq!(
+            format!(
+                "{:#}",
+                step.replace_variables(Resolvers::global()).unwrap_err()
+            ),
+            "Could not replace the variables of `name`: \
+             `file./run/secrets/tag` is a secret, it can't be used in a value that is logged"
+        );
+
+        step.name = None;
+        step.uses = "${{ env.VARS_TEST_ACTION }}".to_string();
+        assert_eq!(
+            format!(
+                "{:#}",
+                step.replace_variables(Resolvers::global()).unwrap_err()
+            ),
+            "Could not replace the variables of `uses`: \
+             `env.VARS_TEST_ACTION` is a secret, it can't be used in a value that is logged"
+        );
+    }
+
+    #[test]
+    fn uses_every_resolver() {
+        struct Fixed;
+
+        impl Resolver for Fixed {
+            fn root(&self) -> &'static str {
+                "env"
+            }
+
+            fn resolve(&self, path: &[String]) -> Result<Value> {
+                Ok(Value::String(format!("fixed-{}", path.join("."))))
+            }
+
+            fn is_secret(&self) -> bool {
+                false
+            }
+        }
+
+        let resolvers = Resolvers::new().with(Fixed);
+        let mut value = "${{ env.TAG }}".to_string();
+        resolvers.replace(&mut value).unwrap();
+        assert_eq!(value, "fixed-TAG");
+
+        let mut value = "${{ steps.build.outputs.tag }}".to_string();
+        resolvers.replace(&mut value).unwrap();
+        assert_eq!(value, "${{ steps.build.outputs.tag }}");
+
+        let mut value = StepValue::String("${{ env.TAG }}".to_string());
+        resolvers.replace_secret(&mut value).unwrap();
+        assert_eq!(value, StepValue::String("fixed-TAG".to_string()));
+        assert_eq!(
+            Resolvers::new()
+                .lookup(&["env".to_string()])
+                .unwrap_err()
+                .to_string(),
+            "Unknown variable 'env'"
+        );
+    }
+
+    #[test]
+    fn reads_secrets_from_files_and_credentials() {
+        let dir = std::env::temp_dir().join(format!("vars_secrets_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("github"), "from-credentials\n").unwrap();
+        std::fs::write(dir.join("github.token"), "dotted").unwrap();
+        std::fs::write(dir.join("docker.secret"), "from-file").unwrap();
+        std::env::set_var(CREDENTIALS_DIRECTORY, &dir);
+
+        let replace = |value: &str| {
+            let mut value = value.to_string();
+            Resolvers::global().replace(&mut value).map(|_| value)
+        };
+
+        assert_eq!(
+            replace("${{ credentials.github }}").unwrap(),
+            "from-credentials"
+        );
+        assert_eq!(replace("${{credentials.github.token}}").unwrap(), "dotted");
+        assert!(replace("${{ credentials.../github }}").is_err());
+        assert_eq!(
+            replace(&format!("${{{{ file.{}/docker.secret }}}}", dir.display())).unwrap(),
+            "from-file"
+        );
+        assert_eq!(
+            replace("${{ file./does/not/exist || 'default' }}").unwrap(),
+            "default"
+        );
+        assert_eq!(
+            format!("{:#}", replace("${{ credentials.missing }}").unwrap_err()),
+            "The expression 'credentials.missing' has no value"
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
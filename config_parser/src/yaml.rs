@@ -0,0 +1,117 @@
+//! A YAML document as a tree of nodes that keep their location in the source, which serde
+//! doesn't provide.
+
+use yaml_rust2::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust2::scanner::{Marker, ScanError, TScalarStyle};
+
+/// A node of the YAML document with the location where it starts, columns start at 1.
+#[derive(Debug)]
+pub(crate) struct Node {
+    pub line: usize,
+    pub column: usize,
+    /// Index of the first character of the node in the source.
+    pub index: usize,
+    pub kind: Kind,
+}
+
+#[derive(Debug)]
+pub(crate) enum Kind {
+    Scalar { value: String, plain: bool },
+    List(Vec<Node>),
+    Map(Vec<(Node, Node)>),
+    Alias,
+}
+
+impl Node {
+    fn new(mark: Marker, kind: Kind) -> Node {
+        Node {
+            line: mark.line(),
+            column: mark.col() + 1,
+            index: mark.index(),
+            kind,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match &self.kind {
+            Kind::Scalar { value, .. } => Some(value),
+            _ => None,
+        }
+    }
+
+    /// The entry of a map with the key `key`.
+    pub fn entry(&self, key: &str) -> Option<(&Node, &Node)> {
+        match &self.kind {
+            Kind::Map(entries) => entries
+                .iter()
+                .find(|(name, _)| name.as_str() == Some(key))
+                .map(|(name, value)| (name, value)),
+            _ => None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(&self.kind, Kind::Scalar { value, plain: true } if ["", "~", "null"].contains(&value.as_str()))
+    }
+}
+
+/// Builds the tree of [`Node`]s from the events of the YAML parser.
+#[derive(Default)]
+struct TreeBuilder {
+    /// The lists and maps that are not finished yet, a map can have a key without a value.
+    stack: Vec<(Node, Option<Node>)>,
+    root: Option<Node>,
+}
+
+impl TreeBuilder {
+    fn push(&mut self, node: Node) {
+        let Some((parent, key)) = self.stack.last_mut() else {
+            self.root.get_or_insert(node);
+            return;
+        };
+
+        match &mut parent.kind {
+            Kind::List(items) => items.push(node),
+            Kind::Map(entries) => match key.take() {
+                Some(key) => entries.push((key, node)),
+                None => *key = Some(node),
+            },
+            Kind::Scalar { .. } | Kind::Alias => unreachable!("only lists and maps are pushed"),
+        }
+    }
+}
+
+impl MarkedEventReceiver for TreeBuilder {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        match event {
+            Event::Scalar(value, style, _, _) => self.push(Node::new(
+                mark,
+                Kind::Scalar {
+                    value,
+                    plain: style == TScalarStyle::Plain,
+                },
+            )),
+            Event::Alias(_) => self.push(Node::new(mark, Kind::Alias)),
+            Event::SequenceStart(_, _) => self
+                .stack
+                .push((Node::new(mark, Kind::List(Vec::new())), None)),
+            Event::MappingStart(_, _) => self
+                .stack
+                .push((Node::new(mark, Kind::Map(Vec::new())), None)),
+            Event::SequenceEnd | Event::MappingEnd => {
+                if let Some((node, _)) = self.stack.pop() {
+                    self.push(node);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Parses the first document of `source`, `None` if there is none.
+pub(crate) fn parse(source: &str) -> Result<Option<Node>, ScanError> {
+    let mut builder = TreeBuilder::default();
+    Parser::new_from_str(source).load(&mut builder, false)?;
+
+    Ok(builder.root)
+}
@@ -0,0 +1,165 @@
+//! Host bindings for plugins built as a component against `shared/wit/validator.wit`.
+
+use shared::http::{HttpMethod, HttpVersion};
+
+wasmtime::component::bindgen!({
+    path: "../shared/wit",
+    world: "validator",
+    async: true,
+});
+
+pub use self::webhook_handler::plugin::types::{
+    HttpMethod as WitHttpMethod, HttpVersion as WitHttpVersion, Response, Value as WitValue,
+};
+
+/// Components start with the same magic bytes as core modules, but use the layer `1`.
+pub fn is_component(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"\0asm") && bytes.get(6..8) == Some(&[1, 0])
+}
+
+impl From<HttpMethod> for WitHttpMethod {
+    fn from(value: HttpMethod) -> Self {
+        match value {
+            HttpMethod::GET => WitHttpMethod::Get,
+            HttpMethod::HEAD => WitHttpMethod::Head,
+            HttpMethod::POST => WitHttpMethod::Post,
+            HttpMethod::PUT => WitHttpMethod::Put,
+            HttpMethod::DELETE => WitHttpMethod::Delete,
+            HttpMethod::CONNECT => WitHttpMethod::Connect,
+            HttpMethod::OPTIONS => WitHttpMethod::Options,
+            HttpMethod::TRACE => WitHttpMethod::Trace,
+            HttpMethod::PATCH => WitHttpMethod::Patch,
+        }
+    }
+}
+
+impl From<HttpVersion> for WitHttpVersion {
+    fn from(value: HttpVersion) -> Self {
+        match value {
+            HttpVersion::Http0_9 => WitHttpVersion::Http09,
+            HttpVersion::Http1_0 => WitHttpVersion::Http10,
+            HttpVersion::Http1_1 => WitHttpVersion::Http11,
+            HttpVersion::Http2 => WitHttpVersion::Http2,
+            HttpVersion::Http3 => WitHttpVersion::Http3,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugin::{PluginInstance, PluginLimits, PluginLoader};
+
+    /// A component that rejects every request, the status of the response is the integer of the
+    /// first key of `arguments`, its headers are the headers of the request and its body is the
+    /// string of the first key of `with`.
+    const TEAPOT: &str = r#"(component
+        (core module $validator
+            (memory (export "memory") 1)
+            (global $heap (mut i32) (i32.const 1024))
+            (func (export "realloc") (param i32 i32) (param $align i32) (param $len i32) (result i32)
+                (local $ptr i32)
+                (local.set $ptr
+                    (i32.and
+                        (i32.add (global.get $heap) (i32.sub (local.get $align) (i32.const 1)))
+                        (i32.sub (i32.const 0) (local.get $align))))
+                (global.set $heap (i32.add (local.get $ptr) (local.get $len)))
+                (local.get $ptr))
+            (func (export "setup") (result i32)
+                (i32.store8 (i32.const 0) (i32.const 0))
+                (i32.const 0))
+            (func (export "http-validator")
+                (param $body i32) (param $body_len i32) (param $headers i32) (param $headers_len i32)
+                (param $method i32) (param $version i32) (param $arguments i32) (param $arguments_len i32)
+                (param $with i32) (param $with_len i32) (result i32)
+                ;; an item of the arguments is 24 bytes, the key is at 0, the case of the value
+                ;; at 8 and its payload at 16
+                (i32.store8 (i32.const 16) (i32.const 1))
+                (i32.store16 (i32.const 20) (i32.wrap_i64 (i64.load offset=16 (local.get $arguments))))
+                (i32.store (i32.const 24) (local.get $headers))
+                (i32.store (i32.const 28) (local.get $headers_len))
+                (i32.store (i32.const 32) (i32.load offset=16 (local.get $with)))
+                (i32.store (i32.const 36) (i32.load offset=20 (local.get $with)))
+                (i32.const 16)))
+        (core instance $instance (instantiate $validator))
+        (type $http-method' (enum "get" "head" "post" "put" "delete" "connect" "options" "trace" "patch"))
+        (export $http-method "http-method" (type $http-method'))
+        (type $http-version' (enum "http09" "http10" "http11" "http2" "http3"))
+        (export $http-version "http-version" (type $http-version'))
+        (type $headers (list (tuple string string)))
+        (type $value' (variant
+            (case "null")
+            (case "bool" bool)
+            (case "integer" s64)
+            (case "float" float64)
+            (case "string" string)
+            (case "json" string)))
+        (export $value "value" (type $value'))
+        (type $arguments (list (tuple string $value)))
+        (type $request' (record
+            (field "body" (list u8))
+            (field "headers" $headers)
+            (field "method" $http-method)
+            (field "version" $http-version)))
+        (export $request "request" (type $request'))
+        (type $response' (record
+            (field "status" u16)
+            (field "headers" $headers)
+            (field "body" (list u8))))
+        (export $response "response" (type $response'))
+        (type $validation-result' (variant
+            (case "accept")
+            (case "reject" $response)
+            (case "error" string)))
+        (export $validation-result "validation-result" (type $validation-result'))
+        (func (export "setup") (result (result (error string)))
+            (canon lift (core func $instance "setup") (memory $instance "memory")))
+        (func (export "http-validator")
+            (param "request" $request) (param "arguments" $arguments) (param "with" $arguments)
+            (result $validation-result)
+            (canon lift (core func $instance "http-validator")
+                (memory $instance "memory") (realloc (func $instance "realloc")))))"#;
+
+    #[tokio::test]
+    async fn runs_validator_of_component() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("glue_component_{}.wasm", std::process::id()));
+        std::fs::write(&path, wat::parse_str(TEAPOT)?)?;
+
+        let plugin = PluginLoader::new(None)?
+            .load(&path, PluginLimits::default())
+            .await;
+        std::fs::remove_file(&path)?;
+
+        let (PluginInstance::Component(validator), store) = plugin?.instantiate().await? else {
+            panic!("The plugin is not a component");
+        };
+        let result = validator
+            .call_http_validator(
+                &mut *store.lock().await,
+                &webhook_handler::plugin::types::Request {
+                    body: b"{}".to_vec(),
+                    headers: vec![("x-event".to_string(), "push".to_string())],
+                    method: HttpMethod::POST.into(),
+                    version: HttpVersion::Http1_1.into(),
+                },
+                &vec![("status".to_string(), WitValue::Integer(418))],
+                &vec![(
+                    "greeting".to_string(),
+                    WitValue::String("hello".to_string()),
+                )],
+            )
+            .await?;
+
+        let ValidationResult::Reject(response) = result else {
+            panic!("Expected a rejection, got {:?}", result);
+        };
+        assert_eq!(response.status, 418);
+        assert_eq!(
+            response.headers,
+            [("x-event".to_string(), "push".to_string())]
+        );
+        assert_eq!(response.body, b"hello");
+
+        Ok(())
+    }
+}
@@ -5,9 +5,9 @@ use anyhow::Result;
 use shared::constants::MAX_ERR_MSG_LEN;
 use tokio::sync::Mutex;
 use wasmtime::{Instance, Store};
-use wasmtime_wasi::WasiP1Ctx;
 
 use crate::exports::{fct_err_clear, fct_get_err_msg, fct_get_err_no};
+use crate::plugin::PluginState;
 use crate::wasm_memory::get_slice;
 
 #[derive(Debug)]
@@ -17,9 +17,17 @@ pub struct CustomError {
 }
 
 impl CustomError {
+    pub fn code(&self) -> i32 {
+        self.code
+    }
+
+    pub fn msg(&self) -> &str {
+        &self.msg
+    }
+
     pub async fn from_wasm(
         instance: Arc<Instance>,
-        store: Arc<Mutex<Store<WasiP1Ctx>>>,
+        store: Arc<Mutex<Store<PluginState>>>,
     ) -> Result<Option<Self>> {
         let fct_err_no = fct_get_err_no(instance.clone(), store.clone()).await?;
         let fct_err_msg = fct_get_err_msg(instance.clone(), store.clone()).await?;
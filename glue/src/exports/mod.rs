@@ -1,10 +1,13 @@
+#![allow(clippy::needless_question_mark)]
+
 use std::future::Future;
 use std::sync::Arc;
 
 use anyhow::{Context, Result};
 use tokio::sync::Mutex;
 use wasmtime::{Instance, Memory, Store};
-use wasmtime_wasi::WasiP1Ctx;
+
+use crate::plugin::PluginState;
 
 // use paste::paste;
 // macro_rules! wasm_export_function {
@@ -13,7 +16,7 @@ use wasmtime_wasi::WasiP1Ctx;
 //             #[doc = "Wrapper function to get the exported function `" $name "` from wasm."]
 //             pub async fn [<fct_ $name>](
 //                 instance: Arc<Instance>,
-//                 store: Arc<Mutex<Store<WasiP1Ctx>>>
+//                 store: Arc<Mutex<Store<PluginState>>>
 //             ) ->  Result<impl FnOnce($($ident_type)*) -> impl Future<Output = Result<$output_type>>>  {
 //                 let wasm_fct = instance.get_typed_func(&mut *store.lock().await, stringify!($name))?;
 //
@@ -33,7 +36,7 @@ use wasmtime_wasi::WasiP1Ctx;
 
 pub async fn fct_alloc(
     instance: Arc<Instance>,
-    store: Arc<Mutex<Store<WasiP1Ctx>>>,
+    store: Arc<Mutex<Store<PluginState>>>,
 ) -> Result<impl FnOnce(usize) -> impl Future<Output = Result<i32>>> {
     let wasm_fct = instance.get_typed_func::<i32, i32>(&mut *store.lock().await, "alloc")?;
 
@@ -46,7 +49,7 @@ pub async fn fct_alloc(
 
 pub async fn fct_dealloc(
     instance: Arc<Instance>,
-    store: Arc<Mutex<Store<WasiP1Ctx>>>,
+    store: Arc<Mutex<Store<PluginState>>>,
 ) -> Result<impl FnOnce(i32, usize) -> impl Future<Output = Result<()>>> {
     let wasm_fct =
         instance.get_typed_func::<(i32, i32), ()>(&mut *store.lock().await, "dealloc")?;
@@ -60,7 +63,7 @@ pub async fn fct_dealloc(
 
 pub async fn fct_get_err_no(
     instance: Arc<Instance>,
-    store: Arc<Mutex<Store<WasiP1Ctx>>>,
+    store: Arc<Mutex<Store<PluginState>>>,
 ) -> Result<impl FnOnce() -> impl Future<Output = Result<i32>>> {
     let wasm_fct = instance.get_typed_func::<(), i32>(&mut *store.lock().await, "get_err_no")?;
 
@@ -73,7 +76,7 @@ pub async fn fct_get_err_no(
 
 pub async fn fct_get_err_msg(
     instance: Arc<Instance>,
-    store: Arc<Mutex<Store<WasiP1Ctx>>>,
+    store: Arc<Mutex<Store<PluginState>>>,
 ) -> Result<impl FnOnce() -> impl Future<Output = Result<i32>>> {
     let wasm_fct = instance.get_typed_func::<(), i32>(&mut *store.lock().await, "get_err_msg")?;
 
@@ -86,7 +89,7 @@ pub async fn fct_get_err_msg(
 
 pub async fn fct_err_clear(
     instance: Arc<Instance>,
-    store: Arc<Mutex<Store<WasiP1Ctx>>>,
+    store: Arc<Mutex<Store<PluginState>>>,
 ) -> Result<impl FnOnce() -> impl Future<Output = Result<()>>> {
     let wasm_fct = instance.get_typed_func::<(), ()>(&mut *store.lock().await, "err_clear")?;
 
@@ -99,7 +102,7 @@ pub async fn fct_err_clear(
 
 pub async fn fct_setup(
     instance: Arc<Instance>,
-    store: Arc<Mutex<Store<WasiP1Ctx>>>,
+    store: Arc<Mutex<Store<PluginState>>>,
 ) -> Result<impl FnOnce() -> impl Future<Output = Result<i32>>> {
     let wasm_fct = instance.get_typed_func::<(), i32>(&mut *store.lock().await, "_setup")?;
 
@@ -110,8 +113,36 @@ pub async fn fct_setup(
     })
 }
 
+pub async fn fct_get_response_ptr(
+    instance: Arc<Instance>,
+    store: Arc<Mutex<Store<PluginState>>>,
+) -> Result<impl FnOnce() -> impl Future<Output = Result<i32>>> {
+    let wasm_fct =
+        instance.get_typed_func::<(), i32>(&mut *store.lock().await, "get_response_ptr")?;
+
+    Ok(move || async move {
+        let mut store = store.lock().await;
+
+        Ok(wasm_fct.call_async(&mut *store, ()).await?)
+    })
+}
+
+pub async fn fct_get_response_len(
+    instance: Arc<Instance>,
+    store: Arc<Mutex<Store<PluginState>>>,
+) -> Result<impl FnOnce() -> impl Future<Output = Result<i32>>> {
+    let wasm_fct =
+        instance.get_typed_func::<(), i32>(&mut *store.lock().await, "get_response_len")?;
+
+    Ok(move || async move {
+        let mut store = store.lock().await;
+
+        Ok(wasm_fct.call_async(&mut *store, ()).await?)
+    })
+}
+
 #[inline]
-pub fn get_memory(instance: &Instance, store: &mut Store<WasiP1Ctx>) -> Result<Memory> {
+pub fn get_memory(instance: &Instance, store: &mut Store<PluginState>) -> Result<Memory> {
     instance
         .get_memory(store, "memory")
         .context("expected memory not found")
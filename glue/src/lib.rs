@@ -1,6 +1,9 @@
 #![feature(impl_trait_in_fn_trait_return)]
 #![feature(unboxed_closures)]
 
+pub mod component;
 pub mod error;
 pub mod exports;
+pub mod plugin;
+pub mod response;
 pub mod wasm_memory;
@@ -0,0 +1,593 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+use wasmtime::component::{self, Component, ResourceTable};
+use wasmtime::{Engine, Instance, InstancePre, Linker, Module, ResourceLimiter, Store, Trap};
+use wasmtime_wasi::{WasiCtx, WasiCtxBuilder, WasiP1Ctx, WasiView};
+
+use crate::component::{is_component, Validator};
+use crate::error::CustomError;
+use crate::exports::fct_setup;
+
+/// How often the epoch of an engine is incremented, this is the resolution of [`PluginLimits::timeout`].
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+/// Resources a single call of a plugin may use before it gets killed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PluginLimits {
+    /// Wall-clock time for instantiating the plugin and running the validator.
+    pub timeout: Duration,
+    /// Maximum size of a linear memory in bytes.
+    pub max_memory: usize,
+    /// Maximum number of elements of a table.
+    pub max_table_elements: u32,
+}
+
+impl Default for PluginLimits {
+    fn default() -> Self {
+        PluginLimits {
+            timeout: Duration::from_secs(10),
+            max_memory: 64 << 20, // 64MiB
+            max_table_elements: 10_000,
+        }
+    }
+}
+
+/// The reason why a plugin was killed by the host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LimitExceeded {
+    Timeout(Duration),
+    Memory { desired: usize, limit: usize },
+    TableElements { desired: u32, limit: u32 },
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitExceeded::Timeout(timeout) => write!(
+                f,
+                "The plugin was killed because it ran longer than {}ms",
+                timeout.as_millis()
+            ),
+            LimitExceeded::Memory { desired, limit } => write!(
+                f,
+                "The plugin was killed because it tried to grow its memory to {} bytes, the limit is {} bytes",
+                desired, limit
+            ),
+            LimitExceeded::TableElements { desired, limit } => write!(
+                f,
+                "The plugin was killed because it tried to grow a table to {} elements, the limit is {} elements",
+                desired, limit
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+/// Data of the store of a plugin instance.
+pub struct PluginState {
+    wasi: WasiP1Ctx,
+    limits: PluginLimits,
+}
+
+impl WasiView for PluginState {
+    fn table(&mut self) -> &mut ResourceTable {
+        self.wasi.table()
+    }
+
+    fn ctx(&mut self) -> &mut WasiCtx {
+        self.wasi.ctx()
+    }
+}
+
+impl ResourceLimiter for PluginState {
+    fn memory_growing(
+        &mut self,
+        _current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> Result<bool> {
+        if desired > self.limits.max_memory {
+            bail!(LimitExceeded::Memory {
+                desired,
+                limit: self.limits.max_memory,
+            });
+        }
+
+        Ok(true)
+    }
+
+    fn table_growing(
+        &mut self,
+        _current: u32,
+        desired: u32,
+        _maximum: Option<u32>,
+    ) -> Result<bool> {
+        if desired > self.limits.max_table_elements {
+            bail!(LimitExceeded::TableElements {
+                desired,
+                limit: self.limits.max_table_elements,
+            });
+        }
+
+        Ok(true)
+    }
+}
+
+#[derive(Clone)]
+enum PluginPre {
+    Module(InstancePre<PluginState>),
+    Component(component::InstancePre<PluginState>),
+}
+
+/// A compiled and linked wasm plugin, either a core module using the raw `glue::exports` abi
+/// or a component built against `shared/wit/validator.wit`.
+///
+/// The plugin itself holds no state, every call to [`Plugin::instantiate`] creates a new store,
+/// so concurrent requests don't share the linear memory of the plugin.
+#[derive(Clone)]
+pub struct Plugin {
+    engine: Engine,
+    pre: PluginPre,
+    limits: PluginLimits,
+}
+
+/// A single instance of a [`Plugin`] together with its own store.
+pub enum PluginInstance {
+    Module(Arc<Instance>),
+    Component(Validator),
+}
+
+/// Increments the epoch of `engine` every [`EPOCH_TICK`] until the engine is dropped.
+fn spawn_epoch_ticker(engine: &Engine) {
+    let engine = engine.weak();
+
+    std::thread::spawn(move || {
+        while let Some(engine) = engine.upgrade() {
+            engine.increment_epoch();
+            drop(engine);
+
+            std::thread::sleep(EPOCH_TICK);
+        }
+    });
+}
+
+/// A compiled module or component that can be stored in the cache directory.
+trait Artifact: Sized {
+    fn compile(engine: &Engine, bytes: &[u8]) -> Result<Self>;
+
+    fn serialize(&self) -> Result<Vec<u8>>;
+
+    /// # Safety
+    ///
+    /// `path` must contain an artifact previously written by [`Artifact::serialize`].
+    unsafe fn deserialize_file(engine: &Engine, path: &Path) -> Result<Self>;
+}
+
+impl Artifact for Module {
+    fn compile(engine: &Engine, bytes: &[u8]) -> Result<Self> {
+        Module::new(engine, bytes)
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>> {
+        Module::serialize(self)
+    }
+
+    unsafe fn deserialize_file(engine: &Engine, path: &Path) -> Result<Self> {
+        Module::deserialize_file(engine, path)
+    }
+}
+
+impl Artifact for Component {
+    fn compile(engine: &Engine, bytes: &[u8]) -> Result<Self> {
+        Component::new(engine, bytes)
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>> {
+        Component::serialize(self)
+    }
+
+    unsafe fn deserialize_file(engine: &Engine, path: &Path) -> Result<Self> {
+        Component::deserialize_file(engine, path)
+    }
+}
+
+/// Functions a core module has to export, they are generated by `#[validator]` of the SDK.
+pub const MODULE_EXPORTS: [&str; 10] = [
+    "memory",
+    "alloc",
+    "dealloc",
+    "get_err_no",
+    "get_err_msg",
+    "err_clear",
+    "get_response_ptr",
+    "get_response_len",
+    "_setup",
+    "http_validator",
+];
+
+/// Functions a component has to export, see the world `validator` in `shared/wit/validator.wit`.
+pub const COMPONENT_EXPORTS: [&str; 2] = ["setup", "http-validator"];
+
+/// The exports of [`MODULE_EXPORTS`] or [`COMPONENT_EXPORTS`] that are missing in the plugin,
+/// `bytes` can be a binary or a text file. Only the names are checked, not the signatures.
+pub fn missing_exports(bytes: &[u8]) -> Result<Vec<&'static str>> {
+    let bytes = wat::parse_bytes(bytes)?;
+    let component = is_component(&bytes);
+
+    let mut exports = Vec::new();
+    for payload in wasmparser::Parser::new(0).parse_all(&bytes) {
+        match payload? {
+            // the sections of the core modules inside a component are skipped
+            wasmparser::Payload::ExportSection(reader) if !component => {
+                for export in reader {
+                    exports.push(export?.name.to_string());
+                }
+            }
+            wasmparser::Payload::ComponentExportSection(reader) => {
+                for export in reader {
+                    exports.push(export?.name.0.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let required = if component {
+        &COMPONENT_EXPORTS[..]
+    } else {
+        &MODULE_EXPORTS[..]
+    };
+
+    Ok(required
+        .iter()
+        .filter(|name| !exports.iter().any(|export| export == *name))
+        .copied()
+        .collect())
+}
+
+/// Loads all plugins of a config with one shared engine.
+///
+/// Every wasm file is only compiled once, no matter how many steps use it. If a cache directory
+/// is set, the compiled artifacts are stored there, keyed by the hash of the wasm file, and are
+/// reused on the next start instead of compiling the plugins again.
+pub struct PluginLoader {
+    engine: Engine,
+    cache_dir: Option<PathBuf>,
+    loaded: HashMap<PathBuf, PluginPre>,
+}
+
+impl PluginLoader {
+    /// The artifacts in `cache_dir` are loaded without validation, so the directory must only
+    /// be writable by the webhook handler itself.
+    pub fn new(cache_dir: Option<PathBuf>) -> Result<PluginLoader> {
+        let engine = Engine::new(
+            wasmtime::Config::default()
+                .async_support(true)
+                .wasm_component_model(true)
+                .epoch_interruption(true)
+                .dynamic_memory_guard_size(1 << 20),
+        )?;
+        spawn_epoch_ticker(&engine);
+
+        if let Some(cache_dir) = &cache_dir {
+            std::fs::create_dir_all(cache_dir).with_context(|| {
+                format!(
+                    "Could not create the plugin cache directory '{}'",
+                    cache_dir.display()
+                )
+            })?;
+        }
+
+        Ok(PluginLoader {
+            engine,
+            cache_dir,
+            loaded: HashMap::new(),
+        })
+    }
+
+    pub async fn load(&mut self, path: impl AsRef<Path>, limits: PluginLimits) -> Result<Plugin> {
+        let path = path.as_ref();
+
+        let canonical_path = std::fs::canonicalize(path)
+            .with_context(|| format!("Could not find the wasm plugin '{}'", path.display()))?;
+
+        let pre = match self.loaded.get(&canonical_path) {
+            Some(pre) => pre.clone(),
+            None => {
+                let pre = self
+                    .link(&canonical_path)
+                    .with_context(|| format!("Can't load the wasm plugin '{}'", path.display()))?;
+                self.loaded.insert(canonical_path, pre.clone());
+
+                pre
+            }
+        };
+
+        let plugin = Plugin {
+            engine: self.engine.clone(),
+            pre,
+            limits,
+        };
+
+        // instantiate once so that a broken plugin is already detected at startup
+        plugin
+            .instantiate()
+            .await
+            .map_err(|err| plugin.explain_error(err))
+            .with_context(|| format!("Can't init the wasm plugin '{}'", path.display()))?;
+
+        Ok(plugin)
+    }
+
+    fn link(&self, path: &Path) -> Result<PluginPre> {
+        let bytes = std::fs::read(path)
+            .with_context(|| format!("Could not read the wasm plugin '{}'", path.display()))?;
+
+        if is_component(&bytes) {
+            let mut linker = component::Linker::new(&self.engine);
+            wasmtime_wasi::command::add_to_linker(&mut linker)?;
+
+            let component = self.compile::<Component>(&bytes)?;
+            Ok(PluginPre::Component(linker.instantiate_pre(&component)?))
+        } else {
+            let mut linker = Linker::new(&self.engine);
+            wasmtime_wasi::preview1::add_to_linker_async(&mut linker, |s: &mut PluginState| {
+                &mut s.wasi
+            })?;
+
+            let module = self.compile::<Module>(&bytes)?;
+            Ok(PluginPre::Module(linker.instantiate_pre(&module)?))
+        }
+    }
+
+    /// Path of the cached artifact for `bytes`, the name also contains the compatibility hash
+    /// of the engine, so artifacts of another wasmtime version or config are never picked up.
+    fn artifact_path(&self, bytes: &[u8]) -> Option<PathBuf> {
+        let cache_dir = self.cache_dir.as_ref()?;
+
+        let mut hasher = DefaultHasher::new();
+        self.engine
+            .precompile_compatibility_hash()
+            .hash(&mut hasher);
+
+        Some(cache_dir.join(format!(
+            "{}-{:016x}.cwasm",
+            hex::encode(Sha256::digest(bytes)),
+            hasher.finish()
+        )))
+    }
+
+    fn compile<T: Artifact>(&self, bytes: &[u8]) -> Result<T> {
+        let Some(artifact_path) = self.artifact_path(bytes) else {
+            return T::compile(&self.engine, bytes);
+        };
+
+        if artifact_path.exists() {
+            // SAFETY: the cache directory is only written by `PluginLoader::compile`, see `PluginLoader::new`
+            match unsafe { T::deserialize_file(&self.engine, &artifact_path) } {
+                Ok(artifact) => {
+                    debug!("Loaded the cached plugin '{}'", artifact_path.display());
+
+                    return Ok(artifact);
+                }
+                Err(err) => warn!(
+                    "Ignoring the cached plugin '{}': {:#}",
+                    artifact_path.display(),
+                    err
+                ),
+            }
+        }
+
+        let artifact = T::compile(&self.engine, bytes)?;
+
+        // write to a temporary file first, so that a crash never leaves a truncated artifact behind
+        let tmp_path = artifact_path.with_extension("tmp");
+        let written = artifact.serialize().and_then(|serialized| {
+            std::fs::write(&tmp_path, serialized)?;
+            std::fs::rename(&tmp_path, &artifact_path)?;
+
+            Ok(())
+        });
+
+        match written {
+            Ok(()) => debug!("Cached the plugin as '{}'", artifact_path.display()),
+            Err(err) => warn!(
+                "Could not cache the plugin as '{}': {:#}",
+                artifact_path.display(),
+                err
+            ),
+        }
+
+        Ok(artifact)
+    }
+}
+
+impl Plugin {
+    pub fn limits(&self) -> PluginLimits {
+        self.limits
+    }
+
+    /// Replaces the trap of a plugin that was killed because of its [`PluginLimits`]
+    /// with a [`LimitExceeded`] error, other errors are returned unchanged.
+    pub fn explain_error(&self, err: anyhow::Error) -> anyhow::Error {
+        if err.is::<LimitExceeded>() {
+            return err;
+        }
+
+        match err.downcast_ref::<Trap>() {
+            Some(Trap::Interrupt) => LimitExceeded::Timeout(self.limits.timeout).into(),
+            _ => err,
+        }
+    }
+
+    /// Creates a fresh store, instantiates the plugin in it and runs its setup function.
+    pub async fn instantiate(&self) -> Result<(PluginInstance, Arc<Mutex<Store<PluginState>>>)> {
+        let wasi = WasiCtxBuilder::new()
+            .inherit_stderr()
+            .inherit_stdout() // TODO map stdout to maybe log and append with something like: "WASM: "
+            .build_p1();
+        let mut store = Store::new(
+            &self.engine,
+            PluginState {
+                wasi,
+                limits: self.limits,
+            },
+        );
+        store.limiter(|state| state);
+
+        let ticks = self.limits.timeout.as_millis() / EPOCH_TICK.as_millis();
+        store.set_epoch_deadline((ticks as u64).max(1));
+        store.epoch_deadline_trap();
+
+        match &self.pre {
+            PluginPre::Module(pre) => {
+                let instance = Arc::new(pre.instantiate_async(&mut store).await?);
+                let store = Arc::new(Mutex::new(store));
+
+                let fct_setup = fct_setup(instance.clone(), store.clone()).await?;
+                if fct_setup().await? != 0 {
+                    let error = CustomError::from_wasm(instance.clone(), store.clone())
+                        .await?
+                        .context("Could not get the error from wasm")?;
+
+                    bail!("Setup of the wasm module failed: {}", error.msg());
+                }
+
+                Ok((PluginInstance::Module(instance), store))
+            }
+            PluginPre::Component(pre) => {
+                let (validator, _) = Validator::instantiate_pre(&mut store, pre).await?;
+
+                if let Err(err) = validator.call_setup(&mut store).await? {
+                    bail!("Setup of the wasm component failed: {}", err);
+                }
+
+                Ok((
+                    PluginInstance::Component(validator),
+                    Arc::new(Mutex::new(store)),
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn load_wat(name: &str, wat: &str, limits: PluginLimits) -> Result<Plugin> {
+        let path = std::env::temp_dir().join(format!("glue_{}_{}.wat", name, std::process::id()));
+        std::fs::write(&path, wat)?;
+
+        let plugin = PluginLoader::new(None)?.load(&path, limits).await;
+        std::fs::remove_file(&path)?;
+
+        plugin
+    }
+
+    #[tokio::test]
+    async fn kills_plugin_after_timeout() {
+        let limits = PluginLimits {
+            timeout: Duration::from_millis(50),
+            ..Default::default()
+        };
+
+        let err = load_wat(
+            "timeout",
+            r#"(module (func (export "_setup") (result i32) (loop (br 0)) (i32.const 0)))"#,
+            limits,
+        )
+        .await
+        .err()
+        .unwrap();
+
+        assert_eq!(
+            err.downcast_ref::<LimitExceeded>(),
+            Some(&LimitExceeded::Timeout(limits.timeout))
+        );
+    }
+
+    #[tokio::test]
+    async fn kills_plugin_growing_memory() {
+        let limits = PluginLimits {
+            max_memory: 2 << 16,
+            ..Default::default()
+        };
+
+        let err = load_wat(
+            "memory",
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "_setup") (result i32) (drop (memory.grow (i32.const 2))) (i32.const 0)))"#,
+            limits,
+        )
+        .await
+        .err()
+        .unwrap();
+
+        assert_eq!(
+            err.downcast_ref::<LimitExceeded>(),
+            Some(&LimitExceeded::Memory {
+                desired: 3 << 16,
+                limit: 2 << 16
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn reuses_compiled_plugins() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("glue_cache_{}", std::process::id()));
+        let path = dir.join("plugin.wat");
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(
+            &path,
+            r#"(module (func (export "_setup") (result i32) (i32.const 0)))"#,
+        )?;
+
+        let cache_dir = dir.join("cache");
+        let mut loader = PluginLoader::new(Some(cache_dir.clone()))?;
+        loader.load(&path, PluginLimits::default()).await?;
+        loader.load(&path, PluginLimits::default()).await?;
+        assert_eq!(loader.loaded.len(), 1);
+        assert_eq!(std::fs::read_dir(&cache_dir)?.count(), 1);
+
+        // a new loader picks up the artifact written by the first one
+        PluginLoader::new(Some(cache_dir.clone()))?
+            .load(&path, PluginLimits::default())
+            .await?;
+        assert_eq!(std::fs::read_dir(&cache_dir)?.count(), 1);
+
+        std::fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn finds_missing_exports() -> Result<()> {
+        let missing = missing_exports(
+            br#"(module (memory (export "memory") 1) (func (export "_setup") (result i32) (i32.const 0)))"#,
+        )?;
+
+        assert_eq!(
+            missing,
+            MODULE_EXPORTS
+                .into_iter()
+                .filter(|name| !["memory", "_setup"].contains(name))
+                .collect::<Vec<_>>()
+        );
+        assert!(missing_exports(b"not wasm").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,107 @@
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use shared::interop::deserialize;
+use shared::ValidatorResponse;
+use tokio::sync::Mutex;
+use wasmtime::{Instance, Store};
+
+use crate::exports::{fct_get_response_len, fct_get_response_ptr, get_memory};
+use crate::plugin::PluginState;
+use crate::wasm_memory::get_slice;
+
+/// Reads the [`ValidatorResponse`] that the plugin has set before returning
+/// [`shared::MiddlewareResult::Reject`].
+pub async fn response_from_wasm(
+    instance: Arc<Instance>,
+    store: Arc<Mutex<Store<PluginState>>>,
+) -> Result<ValidatorResponse> {
+    let fct_response_ptr = fct_get_response_ptr(instance.clone(), store.clone()).await?;
+    let fct_response_len = fct_get_response_len(instance.clone(), store.clone()).await?;
+
+    let ptr = fct_response_ptr().await?;
+    let len = fct_response_len().await?;
+
+    // both come from the plugin, so they are checked before anything is allocated
+    let memory_size = {
+        let mut store = store.lock().await;
+        get_memory(&instance, &mut store)?.data_size(&*store)
+    };
+    let range = usize::try_from(ptr)
+        .ok()
+        .zip(usize::try_from(len).ok())
+        .and_then(|(ptr, len)| Some(ptr..ptr.checked_add(len)?))
+        .filter(|range| range.end <= memory_size)
+        .with_context(|| {
+            format!(
+                "The response of {} bytes at {} is outside of the memory of the plugin, which has {} bytes",
+                len, ptr, memory_size
+            )
+        })?;
+
+    let mut dst = vec![0u8; range.len()];
+    let copied_bytes_from_wasm =
+        get_slice(&mut dst, range.start, &mut *store.lock().await, &instance)?;
+
+    if copied_bytes_from_wasm != dst.len() {
+        bail!(
+            "Could only copy {} of {} bytes of the response from wasm",
+            copied_bytes_from_wasm,
+            dst.len()
+        );
+    }
+
+    deserialize(&dst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugin::{PluginInstance, PluginLimits, PluginLoader};
+
+    async fn response_at(ptr: i32, len: i32) -> Result<ValidatorResponse> {
+        let path = std::env::temp_dir().join(format!(
+            "glue_response_{}_{}_{}.wat",
+            ptr,
+            len,
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            format!(
+                r#"(module
+                    (memory (export "memory") 1)
+                    (func (export "_setup") (result i32) (i32.const 0))
+                    (func (export "get_response_ptr") (result i32) (i32.const {}))
+                    (func (export "get_response_len") (result i32) (i32.const {})))"#,
+                ptr, len
+            ),
+        )?;
+
+        let plugin = PluginLoader::new(None)?
+            .load(&path, PluginLimits::default())
+            .await;
+        std::fs::remove_file(&path)?;
+
+        let (PluginInstance::Module(instance), store) = plugin?.instantiate().await? else {
+            bail!("Expected a core module");
+        };
+
+        response_from_wasm(instance, store).await
+    }
+
+    #[tokio::test]
+    async fn rejects_response_outside_of_memory() {
+        for (ptr, len) in [(0, -1), (65_000, 1 << 20), (-8, 4), (i32::MAX, i32::MAX)] {
+            let err = response_at(ptr, len).await.unwrap_err();
+
+            assert_eq!(
+                err.to_string(),
+                format!(
+                    "The response of {} bytes at {} is outside of the memory of the plugin, which has 65536 bytes",
+                    len, ptr
+                )
+            );
+        }
+    }
+}
@@ -1,37 +1,45 @@
 use std::fmt::Debug;
 use std::sync::Arc;
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use tokio::sync::Mutex;
 use wasmtime::{Instance, Store};
-use wasmtime_wasi::WasiP1Ctx;
 
-use crate::exports::{fct_alloc, fct_dealloc, get_memory};
+use crate::exports::{fct_alloc, get_memory};
+use crate::plugin::PluginState;
 
 async fn copy_slice(
     data: &[u8],
     instance: Arc<Instance>,
-    store: Arc<Mutex<Store<WasiP1Ctx>>>,
+    store: Arc<Mutex<Store<PluginState>>>,
 ) -> Result<(i32, usize)> {
     let memory = get_memory(&instance, &mut *store.lock().await)?;
 
     let alloc = fct_alloc(instance, store.clone()).await?;
     let ptr = alloc(data.len()).await?;
 
-    unsafe {
-        let raw = memory
-            .data_ptr(&mut *store.lock().await)
-            .offset(ptr as isize);
-        raw.copy_from(data.as_ptr(), data.len());
-    }
+    // the pointer comes from the plugin, so the write is bounds checked by wasmtime
+    let mut store = store.lock().await;
+    usize::try_from(ptr)
+        .ok()
+        .and_then(|offset| memory.write(&mut *store, offset, data).ok())
+        .with_context(|| {
+            format!(
+                "The plugin allocated {} bytes at {}, which is outside of its memory of {} bytes",
+                data.len(),
+                ptr,
+                memory.data_size(&*store)
+            )
+        })?;
 
     Ok((ptr, data.len()))
 }
 
+#[allow(clippy::needless_borrow)]
 pub fn get_slice(
     dst: &mut [u8],
     offset: usize,
-    mut store: &mut Store<WasiP1Ctx>,
+    mut store: &mut Store<PluginState>,
     instance: &Instance,
 ) -> Result<usize> {
     let memory = get_memory(&instance, &mut store)?;
@@ -45,20 +53,22 @@ pub fn get_slice(
     }
 
     let len = dst.len();
-    let data = memory.data_mut(&mut store);
-    dst.copy_from_slice(&data[offset..(offset + len).min(memory_size)]);
-
     let copied_data = (offset + len).min(memory_size) - offset;
 
+    let data = memory.data_mut(&mut store);
+    dst[..copied_data].copy_from_slice(&data[offset..offset + copied_data]);
+
     Ok(copied_data)
 }
 
+/// A copy of host bytes in the linear memory of a plugin instance.
+///
+/// The memory is never handed back to the plugin with `dealloc`, every request gets a fresh
+/// store that is thrown away afterwards. Calling into the plugin from `drop` would also trap
+/// once the epoch deadline of the store has passed.
 pub struct WasmMemory {
     ptr: i32,
     len: usize,
-
-    instance: Arc<Instance>,
-    store: Arc<Mutex<Store<WasiP1Ctx>>>,
 }
 
 impl Debug for WasmMemory {
@@ -70,22 +80,16 @@ impl Debug for WasmMemory {
     }
 }
 
+#[allow(clippy::len_without_is_empty)]
 impl WasmMemory {
     pub async fn new(
         bytes: &[u8],
         instance: Arc<Instance>,
-        store: Arc<Mutex<Store<WasiP1Ctx>>>,
+        store: Arc<Mutex<Store<PluginState>>>,
     ) -> Result<Self> {
-        let store_clone = store.clone();
-
-        let (ptr, len) = copy_slice(bytes, instance.clone(), store_clone.clone()).await?;
+        let (ptr, len) = copy_slice(bytes, instance, store).await?;
 
-        Ok(WasmMemory {
-            ptr,
-            len,
-            instance,
-            store: store_clone,
-        })
+        Ok(WasmMemory { ptr, len })
     }
 
     pub fn ptr(&self) -> i32 {
@@ -97,17 +101,59 @@ impl WasmMemory {
     }
 }
 
-impl Drop for WasmMemory {
-    fn drop(&mut self) {
-        async fn inner_drop(obj: &WasmMemory) -> Result<()> {
-            let dealloc = fct_dealloc(obj.instance.clone(), obj.store.clone()).await?;
-            dealloc(obj.ptr(), obj.len()).await?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugin::{PluginInstance, PluginLimits, PluginLoader};
 
-            Ok(())
-        }
+    async fn copy_to(ptr: i32, data: &[u8]) -> Result<WasmMemory> {
+        let path = std::env::temp_dir().join(format!(
+            "glue_wasm_memory_{}_{}.wat",
+            ptr,
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            format!(
+                r#"(module
+                    (memory (export "memory") 1)
+                    (func (export "_setup") (result i32) (i32.const 0))
+                    (func (export "alloc") (param i32) (result i32) (i32.const {})))"#,
+                ptr
+            ),
+        )?;
+
+        let plugin = PluginLoader::new(None)?
+            .load(&path, PluginLimits::default())
+            .await;
+        std::fs::remove_file(&path)?;
+
+        let (PluginInstance::Module(instance), store) = plugin?.instantiate().await? else {
+            bail!("Expected a core module");
+        };
+
+        WasmMemory::new(data, instance, store).await
+    }
 
-        tokio_async_drop::tokio_async_drop!({
-            inner_drop(self).await.unwrap();
-        });
+    #[tokio::test]
+    async fn copies_into_memory() {
+        let memory = copy_to(65_532, b"data").await.unwrap();
+
+        assert_eq!((memory.ptr(), memory.len()), (65_532, 4));
+    }
+
+    #[tokio::test]
+    async fn rejects_allocation_outside_of_memory() {
+        for ptr in [-8, 65_533, 1 << 20, i32::MAX] {
+            let err = copy_to(ptr, b"data").await.unwrap_err();
+
+            assert_eq!(
+                err.to_string(),
+                format!(
+                    "The plugin allocated 4 bytes at {}, which is outside of its memory of 65536 bytes",
+                    ptr
+                )
+            );
+        }
     }
 }
@@ -1,10 +1,36 @@
+use serde::{Deserialize, Serialize};
+
 pub mod constants;
 pub mod http;
 pub mod interop;
+pub mod value;
 
 #[derive(Debug)]
 #[repr(C)]
 pub enum MiddlewareResult {
     Continue = 0,
     Error,
+    /// The request is rejected and the plugin has set a [`ValidatorResponse`] for the caller.
+    Reject,
+}
+
+/// Response that a validator sends back to the caller instead of running the steps.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidatorResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl TryFrom<i32> for MiddlewareResult {
+    type Error = anyhow::Error;
+
+    fn try_from(value: i32) -> anyhow::Result<Self> {
+        match value {
+            0 => Ok(MiddlewareResult::Continue),
+            1 => Ok(MiddlewareResult::Error),
+            2 => Ok(MiddlewareResult::Reject),
+            value => Err(anyhow::anyhow!("Unknown middleware result: '{:?}'", value)),
+        }
+    }
 }
@@ -0,0 +1,1056 @@
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use config_parser::schema::{Action, Key};
+use config_parser::secret::Redactor;
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Full, StreamBody};
+use hyper::body::{Bytes, Frame};
+use hyper::client::conn::http1;
+use hyper::header::{CONTENT_TYPE, HOST};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::rt::TokioIo;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use tokio::net::UnixStream;
+use tokio::sync::mpsc;
+use tracing::{debug, info};
+
+use super::{list, optional, optional_bool, parse_millis_or, required, With};
+use crate::scope::Outputs;
+
+const DEFAULT_SOCKET: &str = "/var/run/docker.sock";
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Characters that are encoded in a segment of the path of a request.
+const PATH_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+const SOCKET: Key = Key::optional(
+    "socket",
+    "Path of the docker socket, default `DOCKER_HOST` or `/var/run/docker.sock`",
+);
+const TIMEOUT: Key = Key::optional(
+    "timeout_ms",
+    "Timeout of each request to docker, default 10 minutes",
+);
+const CONTAINER_NAME: Key = Key::required("container_name", "Name of the container");
+const IMAGE_NAME: Key = Key::required("image_name", "Name of the image");
+
+pub const ACTIONS: [Action; 4] = [
+    Action {
+        name: "docker/ping",
+        description: "Fails if docker isn't reachable",
+        with: &[SOCKET, TIMEOUT],
+    },
+    Action {
+        name: "docker/stop_container",
+        description: "Stops a container, a missing or stopped container is not an error",
+        with: &[SOCKET, TIMEOUT, CONTAINER_NAME],
+    },
+    Action {
+        name: "docker/build_image",
+        description: "Builds an image",
+        with: &[
+            SOCKET,
+            TIMEOUT,
+            IMAGE_NAME,
+            Key::optional("context", "Directory with the build context, default `.`"),
+            Key::optional(
+                "dockerfile",
+                "Path of the Dockerfile in the context, default `Dockerfile`",
+            ),
+        ],
+    },
+    Action {
+        name: "docker/start_image",
+        description: "Creates and starts a container, replaces an existing container with the same name, sets the output `container_id`",
+        with: &[
+            SOCKET,
+            TIMEOUT,
+            CONTAINER_NAME,
+            IMAGE_NAME,
+            Key::optional(
+                "ports",
+                "List of published ports, `[ip:]host:container[/protocol]`",
+            ),
+            Key::optional("networks", "List of networks to attach the container to"),
+            Key::optional("auto_remove", "Removes the container once it stops"),
+        ],
+    },
+];
+
+/// Body of a request to docker.
+type Body = BoxBody<Bytes, io::Error>;
+
+fn full(body: impl Into<Bytes>) -> Body {
+    Full::new(body.into())
+        .map_err(|never| match never {})
+        .boxed()
+}
+
+/// Minimal client for the Docker Engine API, every request opens a new connection to the socket.
+struct Docker {
+    socket: PathBuf,
+    /// Time for a request, from connecting to the socket until the whole response is read.
+    timeout: Duration,
+    /// Redacts the secrets of the step from the logged requests.
+    redactor: Redactor,
+}
+
+impl Docker {
+    /// Uses the `socket` key of `with`, then `DOCKER_HOST` if it points to a unix socket
+    /// and falls back to `/var/run/docker.sock`.
+    fn from_with(with: &With, redactor: &Redactor) -> Result<Docker> {
+        let socket = optional(with, "socket")?
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var("DOCKER_HOST")
+                    .ok()
+                    .and_then(|host| host.strip_prefix("unix://").map(PathBuf::from))
+            })
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_SOCKET));
+
+        Ok(Docker {
+            socket,
+            timeout: parse_millis_or(with, "timeout_ms", DEFAULT_TIMEOUT)?,
+            redactor: redactor.clone(),
+        })
+    }
+
+    async fn request(
+        &self,
+        method: Method,
+        uri: &str,
+        body: Option<(&str, Body)>,
+    ) -> Result<(StatusCode, Bytes)> {
+        let (status, body) = tokio::time::timeout(self.timeout, self.send(&method, uri, body))
+            .await
+            .with_context(|| {
+                format!(
+                    "Docker request '{} {}' got no complete response after {}ms",
+                    method,
+                    uri,
+                    self.timeout.as_millis()
+                )
+            })??;
+
+        debug!(%method, uri = self.redactor.redact(uri), %status, "Docker request");
+
+        Ok((status, body))
+    }
+
+    async fn send(
+        &self,
+        method: &Method,
+        uri: &str,
+        body: Option<(&str, Body)>,
+    ) -> Result<(StatusCode, Bytes)> {
+        let stream = UnixStream::connect(&self.socket).await.with_context(|| {
+            format!(
+                "Could not connect to the docker socket '{}'",
+                self.socket.display()
+            )
+        })?;
+
+        let (mut sender, connection) = http1::handshake(TokioIo::new(stream)).await?;
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                debug!("Connection to the docker socket failed: {:#}", err);
+            }
+        });
+
+        let mut request = Request::builder()
+            .method(method.clone())
+            .uri(uri)
+            .header(HOST, "docker");
+        let body = match body {
+            Some((content_type, body)) => {
+                request = request.header(CONTENT_TYPE, content_type);
+                body
+            }
+            None => full(Bytes::new()),
+        };
+
+        let response = sender
+            .send_request(request.body(body)?)
+            .await
+            .with_context(|| format!("Docker request '{} {}' failed", method, uri))?;
+        let status = response.status();
+        let body = response.into_body().collect().await?.to_bytes();
+
+        Ok((status, body))
+    }
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    message: String,
+}
+
+/// Encodes a name, e.g. of a container, for a segment of the path of a request.
+fn segment(name: &str) -> String {
+    utf8_percent_encode(name, PATH_SEGMENT).to_string()
+}
+
+/// Extracts the message of an error response of the docker api, falls back to the raw body.
+fn error_message(body: &[u8]) -> String {
+    serde_json::from_slice::<ErrorResponse>(body)
+        .map(|err| err.message)
+        .unwrap_or_else(|_| String::from_utf8_lossy(body).trim().to_string())
+}
+
+/// A single line of the progress stream of `/build`.
+#[derive(Deserialize)]
+struct BuildMessage {
+    stream: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct CreateResponse {
+    id: String,
+}
+
+pub async fn ping(with: &With, redactor: &Redactor) -> Result<Outputs> {
+    let (status, body) = Docker::from_with(with, redactor)?
+        .request(Method::GET, "/_ping", None)
+        .await?;
+
+    if !status.is_success() {
+        bail!(
+            "Docker is not healthy ({}): {}",
+            status,
+            error_message(&body)
+        );
+    }
+
+    Ok(Outputs::new())
+}
+
+/// Stops the container `container_name`, a container that doesn't exist or isn't running is ignored.
+pub async fn stop_container(with: &With, redactor: &Redactor) -> Result<Outputs> {
+    let container_name = required(with, "container_name")?;
+    let shown_name = redactor.redact(container_name);
+
+    let (status, body) = Docker::from_with(with, redactor)?
+        .request(
+            Method::POST,
+            &format!("/containers/{}/stop", segment(container_name)),
+            None,
+        )
+        .await?;
+
+    match status {
+        StatusCode::NO_CONTENT => info!(container = shown_name, "Stopped the container"),
+        StatusCode::NOT_MODIFIED => info!(container = shown_name, "Container isn't running"),
+        StatusCode::NOT_FOUND => info!(container = shown_name, "Container doesn't exist"),
+        _ => bail!(
+            "Could not stop the container '{}' ({}): {}",
+            container_name,
+            status,
+            error_message(&body)
+        ),
+    }
+
+    Ok(Outputs::new())
+}
+
+/// The patterns of a `.dockerignore`, the last pattern that matches a path decides if it's
+/// excluded. Supports `*`, `?`, `**` and exceptions with `!`.
+#[derive(Default)]
+struct DockerIgnore {
+    /// The segments of each pattern and if it's an exception.
+    patterns: Vec<(Vec<Vec<char>>, bool)>,
+}
+
+impl DockerIgnore {
+    fn parse(source: &str) -> DockerIgnore {
+        let patterns = source
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let (pattern, exception) = match line.strip_prefix('!') {
+                    Some(pattern) => (pattern.trim(), true),
+                    None => (line, false),
+                };
+                let segments = pattern
+                    .split('/')
+                    .filter(|segment| !segment.is_empty() && *segment != ".")
+                    .map(|segment| segment.chars().collect())
+                    .collect::<Vec<_>>();
+
+                (!segments.is_empty()).then_some((segments, exception))
+            })
+            .collect();
+
+        DockerIgnore { patterns }
+    }
+
+    fn has_exceptions(&self) -> bool {
+        self.patterns.iter().any(|(_, exception)| *exception)
+    }
+
+    /// `path` is relative to the context and separated by `/`, a pattern that matches a
+    /// directory also matches everything in it.
+    fn excludes(&self, path: &str) -> bool {
+        let path = path
+            .split('/')
+            .map(|segment| segment.chars().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        self.patterns
+            .iter()
+            .rev()
+            .find(|(pattern, _)| (1..=path.len()).any(|len| matches_path(pattern, &path[..len])))
+            .is_some_and(|(_, exception)| !exception)
+    }
+}
+
+fn matches_path(pattern: &[Vec<char>], path: &[Vec<char>]) -> bool {
+    match (pattern.split_first(), path.split_first()) {
+        (None, None) => true,
+        (Some((first, rest)), _) if first[..] == ['*', '*'] => {
+            matches_path(rest, path) || (!path.is_empty() && matches_path(pattern, &path[1..]))
+        }
+        (Some((first, rest)), Some((segment, path))) => {
+            matches_segment(first, segment) && matches_path(rest, path)
+        }
+        _ => false,
+    }
+}
+
+fn matches_segment(pattern: &[char], name: &[char]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, None) => true,
+        (Some(('*', rest)), _) => {
+            matches_segment(rest, name)
+                || (!name.is_empty() && matches_segment(pattern, &name[1..]))
+        }
+        (Some(('?', rest)), Some((_, name))) => matches_segment(rest, name),
+        (Some((char, rest)), Some((other, name))) => char == other && matches_segment(rest, name),
+        _ => false,
+    }
+}
+
+/// Writes into the body of a request from a blocking task.
+struct BodyWriter(mpsc::Sender<io::Result<Frame<Bytes>>>);
+
+impl Write for BodyWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .blocking_send(Ok(Frame::data(Bytes::copy_from_slice(buf))))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Docker closed the request"))?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Packs the build context into `writer`, the paths that the `.dockerignore` of the context
+/// excludes are skipped, except for the Dockerfile and the `.dockerignore` itself.
+fn pack_context(context: &Path, dockerfile: &str, writer: impl Write) -> Result<()> {
+    let ignore = match std::fs::read_to_string(context.join(".dockerignore")) {
+        Ok(source) => DockerIgnore::parse(&source),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => DockerIgnore::default(),
+        Err(err) => return Err(err).context("Could not read the .dockerignore"),
+    };
+
+    let mut archive = tar::Builder::new(writer);
+    archive.follow_symlinks(false);
+    append_dir(&mut archive, context, "", &ignore, dockerfile)?;
+    archive.into_inner()?.flush()?;
+
+    Ok(())
+}
+
+fn append_dir(
+    archive: &mut tar::Builder<impl Write>,
+    context: &Path,
+    dir: &str,
+    ignore: &DockerIgnore,
+    dockerfile: &str,
+) -> Result<()> {
+    let mut entries = std::fs::read_dir(context.join(dir))
+        .and_then(|entries| entries.collect::<io::Result<Vec<_>>>())
+        .with_context(|| format!("Could not read the directory '{}'", dir))?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let name = entry.file_name().into_string().map_err(|name| {
+            anyhow!(
+                "The name of '{}' is not valid UTF-8",
+                name.to_string_lossy()
+            )
+        })?;
+        let path = if dir.is_empty() {
+            name
+        } else {
+            format!("{}/{}", dir, name)
+        };
+        let excluded = ignore.excludes(&path) && path != dockerfile && path != ".dockerignore";
+
+        // symlinks are packed as they are, even if they point to a directory
+        if entry.file_type()?.is_dir() {
+            if !excluded {
+                archive.append_dir(&path, entry.path())?;
+            }
+            // an exception can include a path in an excluded directory
+            if !excluded || ignore.has_exceptions() {
+                append_dir(archive, context, &path, ignore, dockerfile)?;
+            }
+        } else if !excluded {
+            archive
+                .append_path_with_name(entry.path(), &path)
+                .with_context(|| format!("Could not pack '{}'", path))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Builds `image_name` from the directory `context` (default `.`) with the
+/// Dockerfile `dockerfile` (default `Dockerfile`), relative to the context.
+///
+/// The context is packed while it's sent and the `.dockerignore` of the context is applied.
+pub async fn build_image(with: &With, redactor: &Redactor) -> Result<Outputs> {
+    let image_name = required(with, "image_name")?;
+    let shown_name = redactor.redact(image_name);
+    let context = PathBuf::from(optional(with, "context")?.unwrap_or("."));
+    let dockerfile = optional(with, "dockerfile")?
+        .map(|dockerfile| dockerfile.trim_start_matches("./"))
+        .unwrap_or("Dockerfile");
+
+    let query = form_urlencoded::Serializer::new(String::new())
+        .append_pair("t", image_name)
+        .append_pair("dockerfile", dockerfile)
+        .finish();
+
+    let (sender, receiver) = mpsc::channel(4);
+    let packing = tokio::task::spawn_blocking({
+        let dockerfile = dockerfile.to_string();
+
+        move || {
+            let writer = BufWriter::with_capacity(64 * 1024, BodyWriter(sender.clone()));
+            if let Err(err) = pack_context(&context, &dockerfile, writer) {
+                // fails the request, unless docker closed it already
+                let _ = sender.blocking_send(Err(io::Error::other(format!(
+                    "Could not pack the build context '{}': {:#}",
+                    context.display(),
+                    err
+                ))));
+            }
+        }
+    });
+    let archive = StreamBody::new(futures::stream::unfold(receiver, |mut receiver| async {
+        receiver.recv().await.map(|frame| (frame, receiver))
+    }));
+
+    let response = Docker::from_with(with, redactor)?
+        .request(
+            Method::POST,
+            &format!("/build?{}", query),
+            Some(("application/x-tar", archive.boxed())),
+        )
+        .await;
+    packing.await?;
+    let (status, body) = response?;
+
+    if !status.is_success() {
+        bail!(
+            "Could not build the image '{}' ({}): {}",
+            image_name,
+            status,
+            error_message(&body)
+        );
+    }
+
+    // the build can still fail after the status was sent, this is only reported in the stream
+    for line in body.split(|byte| *byte == b'\n') {
+        let Ok(message) = serde_json::from_slice::<BuildMessage>(line) else {
+            continue;
+        };
+
+        if let Some(error) = message.error {
+            bail!("Could not build the image '{}': {}", image_name, error);
+        }
+        if let Some(stream) = message.stream.as_deref().map(str::trim) {
+            if !stream.is_empty() {
+                debug!(image = shown_name, "{}", redactor.redact(stream));
+            }
+        }
+    }
+
+    info!(image = shown_name, "Built the image");
+
+    Ok(Outputs::new())
+}
+
+/// Removes the container `container_name` even if it's running, a missing container is ignored.
+async fn remove_container(docker: &Docker, container_name: &str) -> Result<()> {
+    let (status, body) = docker
+        .request(
+            Method::DELETE,
+            &format!("/containers/{}?force=true", segment(container_name)),
+            None,
+        )
+        .await?;
+
+    match status {
+        StatusCode::NO_CONTENT => info!(
+            container = docker.redactor.redact(container_name),
+            "Removed the existing container"
+        ),
+        StatusCode::NOT_FOUND => {}
+        _ => bail!(
+            "Could not remove the existing container '{}' ({}): {}",
+            container_name,
+            status,
+            error_message(&body)
+        ),
+    }
+
+    Ok(())
+}
+
+/// Parses `[host_ip:]host_port:container_port[/protocol]`.
+fn port_binding(port: &str) -> Result<(String, Value)> {
+    let (host, container_port) = port.rsplit_once(':').with_context(|| {
+        format!(
+            "'{}' is not a valid port mapping, expected 'host:container'",
+            port
+        )
+    })?;
+    let (host_ip, host_port) = host.rsplit_once(':').unwrap_or(("", host));
+
+    let container_port = if container_port.contains('/') {
+        container_port.to_string()
+    } else {
+        format!("{}/tcp", container_port)
+    };
+
+    Ok((
+        container_port,
+        json!({ "HostIp": host_ip, "HostPort": host_port }),
+    ))
+}
+
+/// Creates and starts the container `container_name` from `image_name`, sets the output `container_id`.
+/// An existing container with the same name is removed, e.g. if `docker/stop_container` only stopped it.
+///
+/// `ports` and `networks` are lists, the container is attached to all networks.
+pub async fn start_image(with: &With, redactor: &Redactor) -> Result<Outputs> {
+    let container_name = required(with, "container_name")?;
+    let image_name = required(with, "image_name")?;
+    let auto_remove = optional_bool(with, "auto_remove")?;
+    let networks = list(with, "networks")?;
+
+    let mut port_bindings = Map::new();
+    let mut exposed_ports = Map::new();
+    for port in list(with, "ports")? {
+        let (container_port, binding) = port_binding(&port)?;
+
+        exposed_ports.insert(container_port.clone(), json!({}));
+        if let Value::Array(bindings) = port_bindings
+            .entry(container_port)
+            .or_insert_with(|| json!([]))
+        {
+            bindings.push(binding);
+        }
+    }
+
+    let mut host_config = json!({
+        "AutoRemove": auto_remove,
+        "PortBindings": port_bindings,
+    });
+    if let Some(network) = networks.first() {
+        host_config["NetworkMode"] = json!(network);
+    }
+
+    let create = json!({
+        "Image": image_name,
+        "ExposedPorts": exposed_ports,
+        "HostConfig": host_config,
+    });
+
+    let docker = Docker::from_with(with, redactor)?;
+    let query = form_urlencoded::Serializer::new(String::new())
+        .append_pair("name", container_name)
+        .finish();
+
+    let create = Bytes::from(serde_json::to_vec(&create)?);
+    let create_uri = format!("/containers/create?{}", query);
+
+    let (mut status, mut body) = docker
+        .request(
+            Method::POST,
+            &create_uri,
+            Some(("application/json", full(create.clone()))),
+        )
+        .await?;
+    if status == StatusCode::CONFLICT {
+        remove_container(&docker, container_name).await?;
+
+        (status, body) = docker
+            .request(
+                Method::POST,
+                &create_uri,
+                Some(("application/json", full(create))),
+            )
+            .await?;
+    }
+    if !status.is_success() {
+        bail!(
+            "Could not create the container '{}' ({}): {}",
+            container_name,
+            status,
+            error_message(&body)
+        );
+    }
+    let CreateResponse { id } = serde_json::from_slice(&body)
+        .context("Could not parse the response of creating the container")?;
+
+    for network in networks.iter().skip(1) {
+        let (status, body) = docker
+            .request(
+                Method::POST,
+                &format!("/networks/{}/connect", segment(network)),
+                Some((
+                    "application/json",
+                    full(serde_json::to_vec(&json!({ "Container": id }))?),
+                )),
+            )
+            .await?;
+        if !status.is_success() {
+            bail!(
+                "Could not connect the container '{}' to the network '{}' ({}): {}",
+                container_name,
+                network,
+                status,
+                error_message(&body)
+            );
+        }
+    }
+
+    let (status, body) = docker
+        .request(
+            Method::POST,
+            &format!("/containers/{}/start", segment(&id)),
+            None,
+        )
+        .await?;
+    if !status.is_success() && status != StatusCode::NOT_MODIFIED {
+        bail!(
+            "Could not start the container '{}' ({}): {}",
+            container_name,
+            status,
+            error_message(&body)
+        );
+    }
+
+    info!(
+        container = redactor.redact(container_name),
+        image = redactor.redact(image_name),
+        "Started the container"
+    );
+
+    Ok(Outputs::from([("container_id".to_string(), id)]))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+
+    use hyper::body::Incoming;
+    use hyper::server::conn::http1;
+    use hyper::service::service_fn;
+    use hyper::Response;
+    use tokio::net::UnixListener;
+
+    use super::*;
+
+    type Recorded = Arc<Mutex<Vec<(Method, String, Bytes)>>>;
+
+    /// Serves `responses` on a unix socket in `dir`, requests are matched by their method and path,
+    /// all other requests get a `404`. A response is only used once if another response for the
+    /// same request follows it.
+    fn fake_docker(
+        dir: &Path,
+        responses: Vec<(Method, &'static str, StatusCode, &'static str)>,
+    ) -> (With, Recorded) {
+        std::fs::create_dir_all(dir).unwrap();
+        let socket = dir.join("docker.sock");
+        let _ = std::fs::remove_file(&socket);
+
+        let listener = UnixListener::bind(&socket).unwrap();
+        let recorded = Recorded::default();
+        let responses = Arc::new(Mutex::new(responses));
+
+        tokio::spawn({
+            let recorded = recorded.clone();
+
+            async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    let recorded = recorded.clone();
+                    let responses = responses.clone();
+
+                    let service = service_fn(move |request: Request<Incoming>| {
+                        let recorded = recorded.clone();
+                        let responses = responses.clone();
+
+                        async move {
+                            let method = request.method().clone();
+                            let path = request.uri().path().to_string();
+                            let uri = request.uri().to_string();
+                            let body = request.into_body().collect().await?.to_bytes();
+                            recorded.lock().unwrap().push((method.clone(), uri, body));
+
+                            let (status, body) = {
+                                let mut responses = responses.lock().unwrap();
+                                let matching = responses
+                                    .iter()
+                                    .enumerate()
+                                    .filter(|(_, response)| {
+                                        response.0 == method && response.1 == path
+                                    })
+                                    .map(|(index, _)| index)
+                                    .collect::<Vec<_>>();
+
+                                match matching[..] {
+                                    [] => (StatusCode::NOT_FOUND, r#"{"message":"not found"}"#),
+                                    [index] => (responses[index].2, responses[index].3),
+                                    [index, ..] => {
+                                        let response = responses.remove(index);
+                                        (response.2, response.3)
+                                    }
+                                }
+                            };
+
+                            Response::builder()
+                                .status(status)
+                                .body(Full::new(Bytes::from(body)))
+                                .map_err(anyhow::Error::from)
+                        }
+                    });
+
+                    tokio::spawn(
+                        http1::Builder::new().serve_connection(TokioIo::new(stream), service),
+                    );
+                }
+            }
+        });
+
+        let with = With::from([("socket".to_string(), socket.display().to_string().into())]);
+
+        (with, recorded)
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("webhook_handler_{}_{}", name, std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn ping_checks_the_socket() {
+        let dir = test_dir("ping");
+        let (with, _) = fake_docker(&dir, vec![(Method::GET, "/_ping", StatusCode::OK, "OK")]);
+
+        ping(&with, &Redactor::default()).await.unwrap();
+
+        let with = With::from([(
+            "socket".to_string(),
+            dir.join("missing.sock").display().to_string().into(),
+        )]);
+        let err = ping(&with, &Redactor::default()).await.unwrap_err();
+        assert!(format!("{:#}", err).contains("Could not connect to the docker socket"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn requests_time_out() {
+        let dir = test_dir("timeout");
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("docker.sock");
+        let _ = std::fs::remove_file(&socket);
+
+        // accepts the connections, but never answers
+        let listener = UnixListener::bind(&socket).unwrap();
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            loop {
+                connections.push(listener.accept().await.unwrap());
+            }
+        });
+
+        let with = With::from([
+            ("socket".to_string(), socket.display().to_string().into()),
+            ("timeout_ms".to_string(), "50".into()),
+        ]);
+        let err = ping(&with, &Redactor::default()).await.unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            "Docker request 'GET /_ping' got no complete response after 50ms: deadline has elapsed"
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn stop_container_ignores_missing_container() {
+        let dir = test_dir("stop");
+        let (mut with, recorded) = fake_docker(
+            &dir,
+            vec![(
+                Method::POST,
+                "/containers/broken/stop",
+                StatusCode::INTERNAL_SERVER_ERROR,
+                r#"{"message":"cannot stop"}"#,
+            )],
+        );
+
+        with.insert("container_name".to_string(), "my_website".into());
+        stop_container(&with, &Redactor::default()).await.unwrap();
+        assert_eq!(recorded.lock().unwrap()[0].1, "/containers/my_website/stop");
+
+        with.insert("container_name".to_string(), "broken".into());
+        let err = stop_container(&with, &Redactor::default())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().ends_with("cannot stop"));
+
+        with.insert("container_name".to_string(), "my website/../x?y#z".into());
+        stop_container(&with, &Redactor::default()).await.unwrap();
+        assert_eq!(
+            recorded.lock().unwrap()[2].1,
+            "/containers/my%20website%2F..%2Fx%3Fy%23z/stop"
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn build_image_sends_context() {
+        let dir = test_dir("build");
+        let (mut with, recorded) = fake_docker(
+            &dir,
+            vec![(
+                Method::POST,
+                "/build",
+                StatusCode::OK,
+                "{\"stream\":\"Step 1/2 : FROM scratch\"}\n{\"error\":\"no such file\"}\n",
+            )],
+        );
+
+        let context = dir.join("context");
+        std::fs::create_dir_all(&context).unwrap();
+        std::fs::write(context.join("Dockerfile.auto"), "FROM scratch\n").unwrap();
+        std::fs::write(
+            context.join(".dockerignore"),
+            "# build output\ntarget\n*.log\n!keep.log\nDockerfile*\n",
+        )
+        .unwrap();
+        std::fs::create_dir_all(context.join("target/debug")).unwrap();
+        std::fs::write(context.join("target/debug/app"), "").unwrap();
+        std::fs::create_dir_all(context.join("src")).unwrap();
+        std::fs::write(context.join("src/main.rs"), "").unwrap();
+        std::fs::write(context.join("build.log"), "").unwrap();
+        std::fs::write(context.join("keep.log"), "").unwrap();
+
+        with.insert("image_name".to_string(), "my_website_image".into());
+        with.insert("dockerfile".to_string(), "./Dockerfile.auto".into());
+        with.insert("context".to_string(), context.display().to_string().into());
+
+        let err = build_image(&with, &Redactor::default()).await.unwrap_err();
+        assert!(err.to_string().ends_with("no such file"));
+
+        let recorded = recorded.lock().unwrap();
+        let (_, uri, body) = &recorded[0];
+        assert_eq!(uri, "/build?t=my_website_image&dockerfile=Dockerfile.auto");
+
+        let mut archive = tar::Archive::new(body.as_ref());
+        let files = archive
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            files,
+            [
+                ".dockerignore",
+                "Dockerfile.auto",
+                "keep.log",
+                "src",
+                "src/main.rs"
+            ]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dockerignore_matches_like_docker() {
+        let ignore = DockerIgnore::parse(
+            "# comment\n/node_modules\n*.md\n!README.md\n**/*.tmp\ndocs/?.txt\n./cache/\n",
+        );
+
+        for path in [
+            "node_modules",
+            "node_modules/a/index.js",
+            "CHANGELOG.md",
+            "a.tmp",
+            "src/deep/b.tmp",
+            "docs/a.txt",
+            "cache/x",
+        ] {
+            assert!(ignore.excludes(path), "{}", path);
+        }
+        for path in [
+            "README.md",
+            "src/CHANGELOG.md",
+            "docs/ab.txt",
+            "src/node_modules",
+            "# comment",
+        ] {
+            assert!(!ignore.excludes(path), "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn start_image_creates_and_starts_container() {
+        let dir = test_dir("start");
+        let (mut with, recorded) = fake_docker(
+            &dir,
+            vec![
+                (
+                    Method::POST,
+                    "/containers/create",
+                    StatusCode::CREATED,
+                    r#"{"Id":"abc","Warnings":[]}"#,
+                ),
+                (Method::POST, "/networks/other/connect", StatusCode::OK, ""),
+                (
+                    Method::POST,
+                    "/containers/abc/start",
+                    StatusCode::NO_CONTENT,
+                    "",
+                ),
+            ],
+        );
+
+        with.insert("container_name".to_string(), "my_website".into());
+        with.insert("image_name".to_string(), "my_website_image".into());
+        with.insert("networks".to_string(), vec!["internal", "other"].into());
+        with.insert("ports".to_string(), "8080:80".into());
+        with.insert("auto_remove".to_string(), true.into());
+
+        let outputs = start_image(&with, &Redactor::default()).await.unwrap();
+        assert_eq!(outputs["container_id"], "abc");
+
+        let recorded = recorded.lock().unwrap();
+        let uris = recorded
+            .iter()
+            .map(|(_, uri, _)| uri.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            uris,
+            [
+                "/containers/create?name=my_website",
+                "/networks/other/connect",
+                "/containers/abc/start"
+            ]
+        );
+
+        let create: Value = serde_json::from_slice(&recorded[0].2).unwrap();
+        assert_eq!(
+            create,
+            json!({
+                "Image": "my_website_image",
+                "ExposedPorts": { "80/tcp": {} },
+                "HostConfig": {
+                    "AutoRemove": true,
+                    "NetworkMode": "internal",
+                    "PortBindings": { "80/tcp": [{ "HostIp": "", "HostPort": "8080" }] },
+                },
+            })
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn start_image_replaces_existing_container() {
+        let dir = test_dir("replace");
+        let (mut with, recorded) = fake_docker(
+            &dir,
+            vec![
+                (
+                    Method::POST,
+                    "/containers/create",
+                    StatusCode::CONFLICT,
+                    r#"{"message":"Conflict. The container name \"/my_website\" is already in use"}"#,
+                ),
+                (
+                    Method::DELETE,
+                    "/containers/my_website",
+                    StatusCode::NO_CONTENT,
+                    "",
+                ),
+                (
+                    Method::POST,
+                    "/containers/create",
+                    StatusCode::CREATED,
+                    r#"{"Id":"abc","Warnings":[]}"#,
+                ),
+                (
+                    Method::POST,
+                    "/containers/abc/start",
+                    StatusCode::NO_CONTENT,
+                    "",
+                ),
+            ],
+        );
+
+        with.insert("container_name".to_string(), "my_website".into());
+        with.insert("image_name".to_string(), "my_website_image".into());
+
+        let outputs = start_image(&with, &Redactor::default()).await.unwrap();
+        assert_eq!(outputs["container_id"], "abc");
+
+        let recorded = recorded.lock().unwrap();
+        let requests = recorded
+            .iter()
+            .map(|(method, uri, _)| format!("{} {}", method, uri))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            requests,
+            [
+                "POST /containers/create?name=my_website",
+                "DELETE /containers/my_website?force=true",
+                "POST /containers/create?name=my_website",
+                "POST /containers/abc/start"
+            ]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,448 @@
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use config_parser::schema::{Action, Key};
+use config_parser::secret::Redactor;
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
+use hyper::body::Bytes;
+use hyper::{Method, Request, StatusCode};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tracing::{info, warn};
+
+use super::{entries, optional, parse_millis_or, parse_or, required, With};
+use crate::scope::Outputs;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+pub const ACTION: Action = Action {
+    name: "http/request",
+    description: "Sends a request, sets the outputs `status` and `body`",
+    with: &[
+        Key::required("url", "The URL, http or https"),
+        Key::optional("method", "The method, default `GET`"),
+        Key::optional("headers", "Headers as a map or one `Name: value` per line"),
+        Key::optional("body", "The body of the request"),
+        Key::optional(
+            "success_status",
+            "The statuses that count as success, e.g. `200, 3xx`, default `2xx`",
+        ),
+        Key::optional("retries", "Number of retries, default `0`"),
+        Key::optional(
+            "retry_delay_ms",
+            "Delay between the attempts, default `1000`",
+        ),
+        Key::optional("timeout_ms", "Timeout of each attempt, default `30000`"),
+    ],
+};
+
+/// How much of the response body is added to the error of a failed request.
+const MAX_ERROR_BODY_LEN: usize = 256;
+/// Responses with a larger body fail the attempt.
+const MAX_BODY_LEN: usize = 10 << 20;
+
+type HttpsClient = Client<HttpsConnector<HttpConnector>, Full<Bytes>>;
+
+fn client() -> &'static HttpsClient {
+    static CLIENT: OnceLock<HttpsClient> = OnceLock::new();
+
+    CLIENT.get_or_init(|| {
+        let connector = HttpsConnectorBuilder::new()
+            .with_webpki_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+
+        Client::builder(TokioExecutor::new()).build(connector)
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StatusPattern {
+    Exact(u16),
+    /// `2xx` is stored as `Class(2)`.
+    Class(u16),
+}
+
+/// The statuses for which a request counts as successful, e.g. `2xx` or `200, 202, 3xx`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct StatusMatcher(Vec<StatusPattern>);
+
+impl StatusMatcher {
+    fn matches(&self, status: StatusCode) -> bool {
+        let status = status.as_u16();
+
+        self.0.iter().any(|pattern| match pattern {
+            StatusPattern::Exact(exact) => status == *exact,
+            StatusPattern::Class(class) => status / 100 == *class,
+        })
+    }
+}
+
+impl Default for StatusMatcher {
+    fn default() -> Self {
+        StatusMatcher(vec![StatusPattern::Class(2)])
+    }
+}
+
+impl FromStr for StatusMatcher {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let patterns = s
+            .split(',')
+            .map(str::trim)
+            .map(|pattern| {
+                let parsed = match pattern.strip_suffix("xx") {
+                    Some(class) => class.parse().ok().map(StatusPattern::Class),
+                    None => pattern.parse().ok().map(StatusPattern::Exact),
+                };
+
+                parsed.with_context(|| format!("'{}' is not a valid status", pattern))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(StatusMatcher(patterns))
+    }
+}
+
+/// Sends `request` and reads the whole response, both within `timeout`.
+async fn send(request: Request<Full<Bytes>>, timeout: Duration) -> Result<(StatusCode, Bytes)> {
+    let exchange = async {
+        let response = client().request(request).await?;
+        let status = response.status();
+        let body = Limited::new(response.into_body(), MAX_BODY_LEN)
+            .collect()
+            .await
+            .map_err(|err| match err.downcast::<LengthLimitError>() {
+                Ok(_) => anyhow!("The response body is larger than {} bytes", MAX_BODY_LEN),
+                Err(err) => anyhow!(err),
+            })?
+            .to_bytes();
+
+        Ok((status, body))
+    };
+
+    tokio::time::timeout(timeout, exchange)
+        .await
+        .with_context(|| format!("No complete response after {}ms", timeout.as_millis()))?
+}
+
+/// Sends a request to `url` and fails the step if the status doesn't match `success_status`
+/// after all `retries`, `headers` is a map or contains one `Name: value` per line.
+///
+/// Sets the outputs `status` and `body` to the ones of the response. The URL is only logged
+/// with its secrets redacted, e.g. a token in the query.
+pub async fn request(step: &str, with: &With, redactor: &Redactor) -> Result<Outputs> {
+    let url = required(with, "url")?;
+    let shown_url = redactor.redact(url);
+    let method = parse_or(with, "method", Method::GET)?;
+    let body = optional(with, "body")?.unwrap_or_default().to_string();
+    let success_status = parse_or(with, "success_status", StatusMatcher::default())?;
+    let retries = parse_or(with, "retries", 0u32)?;
+    let retry_delay = parse_millis_or(with, "retry_delay_ms", DEFAULT_RETRY_DELAY)?;
+    let timeout = parse_millis_or(with, "timeout_ms", DEFAULT_TIMEOUT)?;
+
+    let headers = entries(with, "headers", ':')?;
+
+    let attempts = retries + 1;
+    for attempt in 1..=attempts {
+        let mut outgoing = Request::builder().method(method.clone()).uri(url);
+        for (name, value) in &headers {
+            outgoing = outgoing.header(name, value);
+        }
+        let outgoing = outgoing
+            .body(Full::new(Bytes::from(body.clone())))
+            .with_context(|| format!("Invalid request to '{}'", shown_url))?;
+
+        let err = match send(outgoing, timeout).await {
+            Ok((status, body)) if success_status.matches(status) => {
+                info!(step, %status, "Request to '{}' succeeded", shown_url);
+
+                return Ok(Outputs::from([
+                    ("status".to_string(), status.as_u16().to_string()),
+                    (
+                        "body".to_string(),
+                        String::from_utf8_lossy(&body).into_owned(),
+                    ),
+                ]));
+            }
+            Ok((status, body)) => {
+                let body = String::from_utf8_lossy(&body[..body.len().min(MAX_ERROR_BODY_LEN)])
+                    .trim()
+                    .to_string();
+
+                anyhow!("Unexpected status {}: {}", status, body)
+            }
+            Err(err) => err,
+        };
+
+        if attempt == attempts {
+            return Err(err.context(format!(
+                "The request to '{}' failed after {} attempt(s)",
+                shown_url, attempts
+            )));
+        }
+
+        warn!(
+            step,
+            attempt,
+            "Request to '{}' failed, retrying: {:#}",
+            shown_url,
+            redactor.redact_error(err)
+        );
+        tokio::time::sleep(retry_delay).await;
+    }
+
+    bail!("The request to '{}' was never sent", shown_url)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+
+    use hyper::body::Incoming;
+    use hyper::server::conn::http1;
+    use hyper::service::service_fn;
+    use hyper::Response;
+    use hyper_util::rt::TokioIo;
+    use shared::value::Value;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    type Recorded = Arc<Mutex<Vec<Request<Bytes>>>>;
+
+    /// Answers the requests with the statuses in `statuses`, one after another.
+    async fn fake_server(statuses: Vec<StatusCode>) -> (SocketAddr, Recorded) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let recorded = Recorded::default();
+
+        tokio::spawn({
+            let recorded = recorded.clone();
+
+            async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    let recorded = recorded.clone();
+                    let statuses = statuses.clone();
+
+                    let service = service_fn(move |request: Request<Incoming>| {
+                        let recorded = recorded.clone();
+                        let statuses = statuses.clone();
+
+                        async move {
+                            let (parts, body) = request.into_parts();
+                            let body = body.collect().await?.to_bytes();
+
+                            let mut recorded = recorded.lock().unwrap();
+                            let status = statuses[recorded.len().min(statuses.len() - 1)];
+                            recorded.push(Request::from_parts(parts, body));
+
+                            Response::builder()
+                                .status(status)
+                                .body(Full::new(Bytes::from("response")))
+                                .map_err(anyhow::Error::from)
+                        }
+                    });
+
+                    tokio::spawn(
+                        http1::Builder::new().serve_connection(TokioIo::new(stream), service),
+                    );
+                }
+            }
+        });
+
+        (address, recorded)
+    }
+
+    /// Answers every request with a chunked `200` whose body never ends, a chunk is sent
+    /// every `delay`.
+    async fn endless_server(delay: Duration) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+
+                tokio::spawn(async move {
+                    // the request has no body, so it ends with the empty line after the headers
+                    let mut request = Vec::new();
+                    while !request.ends_with(b"\r\n\r\n") {
+                        request.push(stream.read_u8().await?);
+                    }
+                    stream
+                        .write_all(b"HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n")
+                        .await?;
+
+                    let chunk = format!("{:x}\r\n{}\r\n", 1 << 16, "x".repeat(1 << 16));
+                    loop {
+                        stream.write_all(chunk.as_bytes()).await?;
+                        tokio::time::sleep(delay).await;
+                    }
+
+                    #[allow(unreachable_code)]
+                    std::io::Result::Ok(())
+                });
+            }
+        });
+
+        address
+    }
+
+    fn with(entries: &[(&str, &str)]) -> With {
+        entries
+            .iter()
+            .map(|(key, value)| (key.to_string(), (*value).into()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_status_matcher() {
+        let matcher = "200, 3xx".parse::<StatusMatcher>().unwrap();
+
+        assert!(matcher.matches(StatusCode::OK));
+        assert!(matcher.matches(StatusCode::NOT_MODIFIED));
+        assert!(!matcher.matches(StatusCode::CREATED));
+        assert!("2yy".parse::<StatusMatcher>().is_err());
+    }
+
+    #[tokio::test]
+    async fn sends_request() {
+        let (address, recorded) = fake_server(vec![StatusCode::OK]).await;
+
+        let mut with = with(&[
+            ("url", &format!("http://{}/deploy/website", address)),
+            ("method", "POST"),
+            ("headers", "content-type: text/plain\nx-event: push"),
+            ("body", "Hello, World!"),
+        ]);
+
+        let outputs = request("notify", &with, &Redactor::default())
+            .await
+            .unwrap();
+        assert_eq!(outputs["status"], "200");
+        assert_eq!(outputs["body"], "response");
+
+        {
+            let requests = recorded.lock().unwrap();
+            assert_eq!(requests.len(), 1);
+            assert_eq!(requests[0].method(), Method::POST);
+            assert_eq!(requests[0].uri(), "/deploy/website");
+            assert_eq!(requests[0].headers()["content-type"], "text/plain");
+            assert_eq!(requests[0].headers()["x-event"], "push");
+            assert_eq!(requests[0].body().as_ref(), b"Hello, World!");
+        }
+
+        with.insert(
+            "headers".to_string(),
+            Value::Map([("x-event".to_string(), "release".into())].into()),
+        );
+        request("notify", &with, &Redactor::default())
+            .await
+            .unwrap();
+        assert_eq!(recorded.lock().unwrap()[1].headers()["x-event"], "release");
+    }
+
+    #[tokio::test]
+    async fn retries_until_success() {
+        let (address, recorded) = fake_server(vec![
+            StatusCode::SERVICE_UNAVAILABLE,
+            StatusCode::SERVICE_UNAVAILABLE,
+            StatusCode::ACCEPTED,
+        ])
+        .await;
+        let url = format!("http://{}/", address);
+
+        let err = request(
+            "notify",
+            &with(&[("url", &url), ("retries", "1"), ("retry_delay_ms", "0")]),
+            &Redactor::default(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            format!(
+                "The request to '{}' failed after 2 attempt(s): Unexpected status 503 Service Unavailable: response",
+                url
+            )
+        );
+
+        request(
+            "notify",
+            &with(&[("url", &url), ("retries", "3"), ("retry_delay_ms", "0")]),
+            &Redactor::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(recorded.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn redacts_secrets_of_the_url() {
+        let (address, _) = fake_server(vec![StatusCode::UNAUTHORIZED]).await;
+
+        let err = request(
+            "notify",
+            &with(&[("url", &format!("http://{}/?token=hunter2", address))]),
+            &Redactor::new(["hunter2".to_string()]),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            format!(
+                "The request to 'http://{}/?token=***' failed after 1 attempt(s): Unexpected status 401 Unauthorized: response",
+                address
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn times_out_reading_the_body() {
+        let address = endless_server(Duration::from_secs(1)).await;
+        let url = format!("http://{}/", address);
+
+        let err = request(
+            "notify",
+            &with(&[("url", &url), ("timeout_ms", "100")]),
+            &Redactor::default(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            format!(
+                "The request to '{}' failed after 1 attempt(s): No complete response after 100ms: deadline has elapsed",
+                url
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn limits_the_body() {
+        let address = endless_server(Duration::ZERO).await;
+        let url = format!("http://{}/", address);
+
+        let err = request("notify", &with(&[("url", &url)]), &Redactor::default())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            format!(
+                "The request to '{}' failed after 1 attempt(s): The response body is larger than 10485760 bytes",
+                url
+            )
+        );
+    }
+}
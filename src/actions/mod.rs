@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use config_parser::internal::StepInternal;
+use config_parser::schema::Action;
+use shared::value::Value;
+
+use crate::scope::Outputs;
+
+mod docker;
+mod http;
+mod shell;
+
+/// The `with` of a step with all expressions evaluated.
+pub type With = HashMap<String, Value>;
+
+/// All actions with the keys of their `with`, which are used for the JSON Schema.
+pub const ACTIONS: [Action; 6] = [
+    docker::ACTIONS[0],
+    docker::ACTIONS[1],
+    docker::ACTIONS[2],
+    docker::ACTIONS[3],
+    http::ACTION,
+    shell::ACTION,
+];
+
+/// The names of all actions, which can be used in `uses`.
+pub fn names() -> Vec<&'static str> {
+    ACTIONS.iter().map(|action| action.name).collect()
+}
+
+/// Runs a step that is implemented by the webhook handler itself instead of a wasm plugin,
+/// the actions redact the secrets of the step from their logs.
+pub async fn execute(step: &StepInternal, with: &With) -> Result<Outputs> {
+    let redactor = step.redactor();
+
+    match step.uses.as_str() {
+        "docker/ping" => docker::ping(with, &redactor).await,
+        "docker/stop_container" => docker::stop_container(with, &redactor).await,
+        "docker/build_image" => docker::build_image(with, &redactor).await,
+        "docker/start_image" => docker::start_image(with, &redactor).await,
+        "http/request" => http::request(step.display_name(), with, &redactor).await,
+        "shell/run" => shell::run(step.display_name(), with, &redactor).await,
+        _ => bail!("Unknown action: '{}'", step.uses),
+    }
+}
+
+/// A value of `with` that has to be a string.
+fn optional<'a>(with: &'a With, key: &str) -> Result<Option<&'a str>> {
+    with.get(key)
+        .map(|value| {
+            value
+                .as_str()
+                .with_context(|| format!("'{}' must be a string, not {}", key, value.type_name()))
+        })
+        .transpose()
+}
+
+fn required<'a>(with: &'a With, key: &str) -> Result<&'a str> {
+    optional(with, key)?.with_context(|| format!("The key '{}' is missing in `with`", key))
+}
+
+fn optional_bool(with: &With, key: &str) -> Result<bool> {
+    with.get(key)
+        .map(|value| {
+            value
+                .as_bool()
+                .with_context(|| format!("'{}' is not a valid value for '{}'", value, key))
+        })
+        .transpose()
+        .map(Option::unwrap_or_default)
+}
+
+/// Parses a bool, number or string, `default` is used if `key` isn't set.
+fn parse_or<T>(with: &With, key: &str, default: T) -> Result<T>
+where
+    T: FromStr,
+    T::Err: Into<anyhow::Error>,
+{
+    let Some(value) = with.get(key) else {
+        return Ok(default);
+    };
+
+    value
+        .to_scalar_string()
+        .context("Expected a bool, a number or a string")
+        .and_then(|scalar| scalar.parse().map_err(Into::into))
+        .with_context(|| format!("'{}' is not a valid value for '{}'", value, key))
+}
+
+/// Parses a number of milliseconds like `timeout_ms`, `default` is used if `key` isn't set.
+fn parse_millis_or(with: &With, key: &str, default: Duration) -> Result<Duration> {
+    parse_or(with, key, default.as_millis() as u64).map(Duration::from_millis)
+}
+
+/// A list like `networks: [a, b]`, a string is split at commas like `networks: a, b`.
+fn list(with: &With, key: &str) -> Result<Vec<String>> {
+    let invalid = || format!("'{}' must be a list of strings or numbers", key);
+
+    match with.get(key) {
+        None => Ok(Vec::new()),
+        Some(Value::List(items)) => items
+            .iter()
+            .map(|item| item.to_scalar_string().with_context(invalid))
+            .collect(),
+        Some(value) => Ok(value
+            .to_scalar_string()
+            .with_context(invalid)?
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(str::to_string)
+            .collect()),
+    }
+}
+
+/// Entries like `env: { KEY: value }`, a string has one entry per line which is split at
+/// `separator`, e.g. `KEY=value`.
+fn entries(with: &With, key: &str, separator: char) -> Result<Vec<(String, String)>> {
+    match with.get(key) {
+        None => Ok(Vec::new()),
+        Some(Value::Map(entries)) => entries
+            .iter()
+            .map(|(name, value)| {
+                let value = value
+                    .to_scalar_string()
+                    .with_context(|| format!("'{}.{}' must be a string or a number", key, name))?;
+
+                Ok((name.clone(), value))
+            })
+            .collect(),
+        Some(value) => value
+            .as_str()
+            .with_context(|| format!("'{}' must be a map or a string", key))?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let (name, value) = line.split_once(separator).with_context(|| {
+                    format!(
+                        "'{}' in `{}` is not of the form '<name>{}<value>'",
+                        line, key, separator
+                    )
+                })?;
+
+                Ok((name.trim().to_string(), value.trim().to_string()))
+            })
+            .collect(),
+    }
+}
@@ -0,0 +1,268 @@
+use std::os::unix::process::CommandExt;
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use config_parser::schema::{Action, Key};
+use config_parser::secret::Redactor;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::Command;
+use tracing::{info, warn};
+
+use super::{entries, optional, parse_or, required, With};
+use crate::scope::Outputs;
+
+const DEFAULT_SHELL: &str = "sh";
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+pub const ACTION: Action = Action {
+    name: "shell/run",
+    description: "Runs a command, sets the output `stdout`",
+    with: &[
+        Key::required("run", "The command"),
+        Key::optional("shell", "The shell to run the command with, default `sh`"),
+        Key::optional("working_directory", "The directory to run the command in"),
+        Key::optional(
+            "env",
+            "Environment variables as a map or one `KEY=value` per line",
+        ),
+        Key::optional("timeout_ms", "Timeout of the command, default 10 minutes"),
+    ],
+};
+
+/// Kills the process group of the command when it's dropped, e.g. after a timeout, so that the
+/// processes the command started don't keep running.
+struct ProcessGroup(Option<u32>);
+
+impl ProcessGroup {
+    /// The command exited, its process group must not be killed anymore as its id can be reused.
+    fn exited(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        if let Some(id) = self.0.and_then(|id| libc::pid_t::try_from(id).ok()) {
+            // SAFETY: sends a signal to the process group of the command, which doesn't
+            // touch the memory of this process
+            unsafe {
+                libc::kill(-id, libc::SIGKILL);
+            }
+        }
+    }
+}
+
+/// Logs every line of `reader` with its secrets redacted until it is closed and returns
+/// everything that was read.
+async fn log_lines(
+    reader: impl AsyncRead + Unpin,
+    step: &str,
+    redactor: &Redactor,
+    stderr: bool,
+) -> Result<String> {
+    let mut reader = BufReader::new(reader);
+    let mut output = String::new();
+    let mut line = Vec::new();
+
+    while reader.read_until(b'\n', &mut line).await? != 0 {
+        let text = String::from_utf8_lossy(&line);
+        output.push_str(&text);
+
+        let text = redactor.redact(text.trim_end());
+        if stderr {
+            warn!(step, "{}", text);
+        } else {
+            info!(step, "{}", text);
+        }
+
+        line.clear();
+    }
+
+    Ok(output)
+}
+
+/// Runs `run` with `shell -c`, the output of the command is logged with the name of the step.
+/// `env` is a map or contains one `KEY=value` per line.
+///
+/// Sets the output `stdout` to everything the command wrote to stdout, without the trailing newline.
+pub async fn run(step: &str, with: &With, redactor: &Redactor) -> Result<Outputs> {
+    let run = required(with, "run")?;
+    let shell = optional(with, "shell")?.unwrap_or(DEFAULT_SHELL);
+    let timeout = Duration::from_millis(parse_or(
+        with,
+        "timeout_ms",
+        DEFAULT_TIMEOUT.as_millis() as u64,
+    )?);
+
+    let mut shell_args = shell.split_whitespace();
+    let program = shell_args.next().context("`shell` is empty")?;
+
+    let mut command = std::process::Command::new(program);
+    // in its own process group, so that the processes it starts can be killed with it
+    command.process_group(0);
+    let mut command = Command::from(command);
+    command
+        .args(shell_args)
+        .arg("-c")
+        .arg(run)
+        .envs(entries(with, "env", '=')?)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    if let Some(working_directory) = optional(with, "working_directory")? {
+        command.current_dir(working_directory);
+    }
+
+    let mut child = command
+        .spawn()
+        .with_context(|| format!("Could not start the shell '{}'", shell))?;
+    let process_group = ProcessGroup(child.id());
+    let stdout = child
+        .stdout
+        .take()
+        .context("stdout of the command is missing")?;
+    let stderr = child
+        .stderr
+        .take()
+        .context("stderr of the command is missing")?;
+
+    let finished = tokio::time::timeout(timeout, async {
+        let (status, stdout, stderr) = tokio::join!(
+            child.wait(),
+            log_lines(stdout, step, redactor, false),
+            log_lines(stderr, step, redactor, true)
+        );
+        stderr?;
+
+        anyhow::Ok((status?, stdout?))
+    })
+    .await;
+
+    let (status, stdout) = match finished {
+        Ok(finished) => {
+            process_group.exited();
+            finished?
+        }
+        Err(_) => {
+            drop(process_group);
+            child.kill().await?;
+            bail!("The command timed out after {}ms", timeout.as_millis());
+        }
+    };
+
+    if !status.success() {
+        bail!("The command failed with {}", status);
+    }
+
+    let stdout = stdout.trim_end_matches(['\r', '\n']).to_string();
+    Ok(Outputs::from([("stdout".to_string(), stdout)]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with(entries: &[(&str, &str)]) -> With {
+        entries
+            .iter()
+            .map(|(key, value)| (key.to_string(), (*value).into()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn runs_command() {
+        let outputs = run(
+            "test",
+            &with(&[("run", "echo hello && echo world >&2")]),
+            &Redactor::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(outputs["stdout"], "hello");
+    }
+
+    #[tokio::test]
+    async fn fails_on_non_zero_exit_status() {
+        let err = run("test", &with(&[("run", "exit 3")]), &Redactor::default())
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.to_string(), "The command failed with exit status: 3");
+    }
+
+    #[tokio::test]
+    async fn fails_on_timeout() {
+        let err = run(
+            "test",
+            &with(&[("run", "sleep 5"), ("timeout_ms", "100")]),
+            &Redactor::default(),
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(err.to_string(), "The command timed out after 100ms");
+    }
+
+    #[tokio::test]
+    async fn kills_started_processes_on_timeout() {
+        let pid_file = std::env::temp_dir().join(format!("shell_{}.pid", std::process::id()));
+
+        let err = run(
+            "test",
+            &with(&[
+                (
+                    "run",
+                    &format!("sleep 30 & echo $! > {}; wait", pid_file.display()),
+                ),
+                ("timeout_ms", "200"),
+            ]),
+            &Redactor::default(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.to_string(), "The command timed out after 200ms");
+
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        std::fs::remove_file(&pid_file).unwrap();
+
+        // the killed process is gone once it's reaped, until then it's a zombie
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let stat =
+            std::fs::read_to_string(format!("/proc/{}/stat", pid.trim())).unwrap_or_default();
+        assert!(
+            stat.is_empty() || stat.contains(") Z "),
+            "`sleep` is still running: {}",
+            stat
+        );
+    }
+
+    #[tokio::test]
+    async fn uses_working_directory_shell_and_env() {
+        let dir = std::env::temp_dir();
+
+        run(
+            "test",
+            &with(&[
+                (
+                    "run",
+                    r#"[ "$PWD" = "$EXPECTED_DIR" ] && [ "$GREETING" = "hello world" ]"#,
+                ),
+                ("shell", "sh -e"),
+                ("working_directory", dir.to_str().unwrap()),
+                (
+                    "env",
+                    &format!(
+                        "GREETING=hello world\nEXPECTED_DIR={}",
+                        dir.canonicalize().unwrap().display()
+                    ),
+                ),
+            ]),
+            &Redactor::default(),
+        )
+        .await
+        .unwrap();
+    }
+}
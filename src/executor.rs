@@ -0,0 +1,581 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use config_parser::internal::StepInternal;
+use config_parser::secret::Redactor;
+use glue::component::{self, ValidationResult, Validator, WitValue};
+use glue::error::CustomError;
+use glue::plugin::{PluginInstance, PluginState};
+use glue::response::response_from_wasm;
+use glue::wasm_memory::WasmMemory;
+use hyper::header::HeaderValue;
+use hyper::HeaderMap;
+use shared::http::{HttpMethod, HttpVersion};
+use shared::interop::serialize;
+use shared::value::Value;
+use shared::{MiddlewareResult, ValidatorResponse};
+use tokio::sync::Mutex;
+use tracing::{debug, error, info, warn};
+use wasmtime::{Instance, Store};
+
+use crate::scope::{Outputs, Scope};
+
+pub struct WrappedRequest<'a> {
+    pub body: &'a [u8],
+    pub headers: HeaderMap<HeaderValue>,
+    pub method: HttpMethod,
+    pub version: HttpVersion,
+    /// Parameters captured from the route path, e.g. `project` for `/deploy/{project}`.
+    pub params: HashMap<String, String>,
+}
+
+#[derive(Debug)]
+pub enum ValidatorOutcome {
+    Continue,
+    /// The plugin rejected the request, with the response for the caller if the plugin set one.
+    Reject(Option<ValidatorResponse>),
+}
+
+/// Runs the wasm plugin of `step`, the secrets of the step are redacted from the error and the
+/// errors the plugin reports.
+pub async fn call_wasm_validator<'a>(
+    request: &WrappedRequest<'a>,
+    step: &StepInternal,
+) -> Result<ValidatorOutcome> {
+    let Some(plugin) = &step.plugin else {
+        bail!("The step '{}' is not a wasm plugin", step.display_name());
+    };
+
+    let redactor = step.redactor();
+    let steps = HashMap::new();
+    let scope = Scope::new(Some(request), &steps);
+    let arguments = scope.render_map(&step.arguments)?;
+    let with = scope.render_map(&step.with)?;
+
+    let result = match plugin.instantiate().await {
+        Ok((PluginInstance::Module(instance), store)) => {
+            call_wasm_module(request, &arguments, &with, &redactor, instance, store).await
+        }
+        Ok((PluginInstance::Component(validator), store)) => {
+            call_wasm_component(request, &arguments, &with, &redactor, &validator, &store).await
+        }
+        Err(err) => Err(err),
+    };
+
+    result.map_err(|err| redactor.redact_error(plugin.explain_error(err)))
+}
+
+fn to_json(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Null => serde_json::Value::Null,
+        Value::Bool(value) => (*value).into(),
+        Value::Integer(value) => (*value).into(),
+        Value::Float(value) => (*value).into(),
+        Value::String(value) => value.clone().into(),
+        Value::List(items) => items.iter().map(to_json).collect(),
+        Value::Map(entries) => entries
+            .iter()
+            .map(|(key, value)| (key.clone(), to_json(value)))
+            .collect(),
+    }
+}
+
+/// WIT has no recursive types, so lists and maps are passed as JSON.
+fn to_wit_value(value: &Value) -> WitValue {
+    match value {
+        Value::Null => WitValue::Null,
+        Value::Bool(value) => WitValue::Bool(*value),
+        Value::Integer(value) => WitValue::Integer(*value),
+        Value::Float(value) => WitValue::Float(*value),
+        Value::String(value) => WitValue::String(value.clone()),
+        value @ (Value::List(_) | Value::Map(_)) => WitValue::Json(to_json(value).to_string()),
+    }
+}
+
+async fn call_wasm_component<'a>(
+    request: &WrappedRequest<'a>,
+    arguments: &HashMap<String, Value>,
+    with: &HashMap<String, Value>,
+    redactor: &Redactor,
+    validator: &Validator,
+    store: &Mutex<Store<PluginState>>,
+) -> Result<ValidatorOutcome> {
+    let to_list = |map: &HashMap<String, Value>| {
+        map.iter()
+            .map(|(key, value)| (key.clone(), to_wit_value(value)))
+            .collect::<Vec<_>>()
+    };
+
+    let wit_request = component::Request {
+        body: request.body.to_vec(),
+        headers: request
+            .headers
+            .iter()
+            .map(|(name, value)| {
+                (
+                    name.to_string(),
+                    String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                )
+            })
+            .collect(),
+        method: request.method.into(),
+        version: request.version.into(),
+    };
+
+    let result = validator
+        .call_http_validator(
+            &mut *store.lock().await,
+            &wit_request,
+            &to_list(arguments),
+            &to_list(with),
+        )
+        .await?;
+
+    match result {
+        ValidationResult::Accept => Ok(ValidatorOutcome::Continue),
+        ValidationResult::Reject(response) => {
+            Ok(ValidatorOutcome::Reject(Some(ValidatorResponse {
+                status: response.status,
+                headers: response.headers,
+                body: response.body,
+            })))
+        }
+        ValidationResult::Error(msg) => {
+            warn!("Plugin reported an error: {}", redactor.redact(&msg));
+
+            Ok(ValidatorOutcome::Reject(None))
+        }
+    }
+}
+
+async fn call_wasm_module<'a>(
+    request: &WrappedRequest<'a>,
+    arguments: &HashMap<String, Value>,
+    with: &HashMap<String, Value>,
+    redactor: &Redactor,
+    instance: Arc<Instance>,
+    store: Arc<Mutex<Store<PluginState>>>,
+) -> Result<ValidatorOutcome> {
+    let fct_http_validator = instance
+        .get_typed_func::<(i32, i32, i32, i32, i32, i32, i32, i32, i32, i32), i32>(
+            &mut *store.lock().await,
+            "http_validator",
+        )?;
+
+    let headers = request
+        .headers
+        .iter()
+        .map(|(name, value)| {
+            (
+                name.to_string(),
+                String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            )
+        })
+        .collect::<HashMap<String, String>>();
+
+    let hashmap = WasmMemory::new(&serialize(&headers)?, instance.clone(), store.clone()).await?;
+    let arguments =
+        WasmMemory::new(&serialize(arguments)?, instance.clone(), store.clone()).await?;
+    let with = WasmMemory::new(&serialize(with)?, instance.clone(), store.clone()).await?;
+
+    let body_wasm = WasmMemory::new(request.body, instance.clone(), store.clone()).await?;
+
+    let request_result = fct_http_validator
+        .call_async(
+            &mut *store.lock().await,
+            (
+                body_wasm.ptr(),
+                body_wasm.len() as i32,
+                hashmap.ptr(),
+                hashmap.len() as i32,
+                request.method as i32,
+                request.version as i32,
+                arguments.ptr(),
+                arguments.len() as i32,
+                with.ptr(),
+                with.len() as i32,
+            ),
+        )
+        .await?;
+
+    if let Some(err) = CustomError::from_wasm(instance.clone(), store.clone()).await? {
+        warn!(
+            code = err.code(),
+            "Plugin reported an error: {}",
+            redactor.redact(err.msg())
+        );
+    }
+
+    match MiddlewareResult::try_from(request_result)? {
+        MiddlewareResult::Continue => Ok(ValidatorOutcome::Continue),
+        MiddlewareResult::Error => Ok(ValidatorOutcome::Reject(None)),
+        MiddlewareResult::Reject => Ok(ValidatorOutcome::Reject(Some(
+            response_from_wasm(instance, store).await?,
+        ))),
+    }
+}
+
+#[derive(Debug)]
+pub enum StepStatus {
+    Success,
+    Failed(anyhow::Error),
+    /// The step didn't run because a previous step failed.
+    Skipped,
+}
+
+#[derive(Debug)]
+pub struct StepReport {
+    pub name: String,
+    pub status: StepStatus,
+}
+
+/// Outcome of every step of a route, in the order they are defined in the config.
+#[derive(Debug, Default)]
+pub struct ExecutionReport {
+    pub steps: Vec<StepReport>,
+}
+
+impl ExecutionReport {
+    pub fn failed_step(&self) -> Option<&StepReport> {
+        self.steps
+            .iter()
+            .find(|step| matches!(step.status, StepStatus::Failed(_)))
+    }
+}
+
+async fn execute_step(
+    step: &StepInternal,
+    request: Option<&WrappedRequest<'_>>,
+    scope: &Scope<'_>,
+) -> Result<Outputs> {
+    debug!(
+        step = step.display_name(),
+        params = ?request.map(|request| &request.params),
+        "Executing step"
+    );
+
+    match &step.plugin {
+        Some(_) => {
+            let Some(request) = request else {
+                bail!("Wasm plugins can only be used in the steps of a route");
+            };
+
+            match call_wasm_validator(request, step).await? {
+                ValidatorOutcome::Continue => Ok(Outputs::new()),
+                ValidatorOutcome::Reject(_) => bail!("The plugin rejected the request"),
+            }
+        }
+        None => crate::actions::execute(step, &scope.render_map(&step.with)?)
+            .await
+            .map_err(|err| step.redactor().redact_error(err)),
+    }
+}
+
+/// Runs the steps one after another and stops at the first step that fails,
+/// all steps after it are marked as [`StepStatus::Skipped`].
+///
+/// `request` is `None` if the steps aren't triggered by a request, e.g. for the health check.
+pub async fn execute_steps(
+    steps: &[StepInternal],
+    request: Option<&WrappedRequest<'_>>,
+) -> ExecutionReport {
+    let mut report = ExecutionReport::default();
+    let mut outputs = HashMap::new();
+    let mut steps = steps.iter();
+
+    for step in steps.by_ref() {
+        let name = step.display_name().to_string();
+
+        let result = execute_step(step, request, &Scope::new(request, &outputs)).await;
+
+        match result {
+            Ok(step_outputs) => {
+                info!(step = name, "Step finished");
+
+                if let Some(id) = &step.id {
+                    outputs.insert(id.clone(), step_outputs);
+                }
+
+                report.steps.push(StepReport {
+                    name,
+                    status: StepStatus::Success,
+                });
+            }
+            Err(err) => {
+                error!(step = name, "Step failed: {:#}", err);
+
+                report.steps.push(StepReport {
+                    name,
+                    status: StepStatus::Failed(err),
+                });
+                break;
+            }
+        }
+    }
+
+    for step in steps {
+        report.steps.push(StepReport {
+            name: step.display_name().to_string(),
+            status: StepStatus::Skipped,
+        });
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use config_parser::secret::Secret;
+    use config_parser::value::StepValue;
+    use glue::plugin::{LimitExceeded, PluginLimits, PluginLoader};
+
+    use super::*;
+
+    /// Where the expected bytes are stored in the memory of [`expecting_plugin`].
+    const EXPECTED_OFFSET: usize = 1024;
+
+    /// A core module that accepts a request if the serialized headers, arguments and `with` it
+    /// gets are `headers`, `arguments` and `with`, otherwise it returns [`MiddlewareResult::Error`].
+    fn expecting_plugin(headers: &[u8], arguments: &[u8], with: &[u8]) -> String {
+        let data = [headers, arguments, with]
+            .concat()
+            .iter()
+            .map(|byte| format!("\\{:02x}", byte))
+            .collect::<String>();
+
+        format!(
+            r#"(module
+                (memory (export "memory") 1)
+                (global $heap (mut i32) (i32.const 32768))
+                (data (i32.const {offset}) "{data}")
+                (func (export "alloc") (param $len i32) (result i32)
+                    (global.get $heap)
+                    (global.set $heap (i32.add (global.get $heap) (local.get $len))))
+                (func (export "dealloc") (param i32 i32))
+                (func (export "get_err_no") (result i32) (i32.const 0))
+                (func (export "get_err_msg") (result i32) (i32.const 0))
+                (func (export "err_clear"))
+                (func (export "get_response_ptr") (result i32) (i32.const 0))
+                (func (export "get_response_len") (result i32) (i32.const 0))
+                (func (export "_setup") (result i32) (i32.const 0))
+                (func $equals (param $a i32) (param $b i32) (param $len i32) (result i32)
+                    (loop $next
+                        (if (local.get $len)
+                            (then
+                                (if (i32.ne (i32.load8_u (local.get $a)) (i32.load8_u (local.get $b)))
+                                    (then (return (i32.const 0))))
+                                (local.set $a (i32.add (local.get $a) (i32.const 1)))
+                                (local.set $b (i32.add (local.get $b) (i32.const 1)))
+                                (local.set $len (i32.sub (local.get $len) (i32.const 1)))
+                                (br $next))))
+                    (i32.const 1))
+                (func (export "http_validator")
+                    (param $body i32) (param $body_len i32) (param $headers i32) (param $headers_len i32)
+                    (param $method i32) (param $version i32) (param $arguments i32) (param $arguments_len i32)
+                    (param $with i32) (param $with_len i32) (result i32)
+                    (if (result i32)
+                        (i32.and
+                            (i32.and
+                                (i32.and
+                                    (i32.eq (local.get $headers_len) (i32.const {headers_len}))
+                                    (i32.eq (local.get $arguments_len) (i32.const {arguments_len})))
+                                (i32.eq (local.get $with_len) (i32.const {with_len})))
+                            (i32.and
+                                (i32.and
+                                    (call $equals (local.get $headers) (i32.const {headers_offset}) (i32.const {headers_len}))
+                                    (call $equals (local.get $arguments) (i32.const {arguments_offset}) (i32.const {arguments_len})))
+                                (call $equals (local.get $with) (i32.const {with_offset}) (i32.const {with_len}))))
+                        (then (i32.const 0))
+                        (else (i32.const 1)))))"#,
+            offset = EXPECTED_OFFSET,
+            data = data,
+            headers_offset = EXPECTED_OFFSET,
+            headers_len = headers.len(),
+            arguments_offset = EXPECTED_OFFSET + headers.len(),
+            arguments_len = arguments.len(),
+            with_offset = EXPECTED_OFFSET + headers.len() + arguments.len(),
+            with_len = with.len(),
+        )
+    }
+
+    fn request(headers: HeaderMap) -> WrappedRequest<'static> {
+        WrappedRequest {
+            body: b"",
+            headers,
+            method: HttpMethod::POST,
+            version: HttpVersion::Http1_1,
+            params: HashMap::new(),
+        }
+    }
+
+    async fn plugin_step(name: &str, wat: &str, limits: PluginLimits) -> StepInternal {
+        let path = std::env::temp_dir().join(format!(
+            "webhook_handler_{}_{}.wat",
+            name,
+            std::process::id()
+        ));
+        std::fs::write(&path, wat).unwrap();
+        let plugin = PluginLoader::new(None)
+            .unwrap()
+            .load(&path, limits)
+            .await
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        StepInternal {
+            uses: name.to_string(),
+            name: None,
+            with: HashMap::new(),
+            arguments: HashMap::new(),
+            id: None,
+            plugin: Some(plugin),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn passes_header_values_that_are_not_ascii() {
+        let expected = serialize(&HashMap::from([("x-name", "caf\u{fffd}")])).unwrap();
+        let empty = serialize(&HashMap::<String, Value>::new()).unwrap();
+        let step = plugin_step(
+            "headers",
+            &expecting_plugin(&expected, &empty, &empty),
+            PluginLimits::default(),
+        )
+        .await;
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-name", HeaderValue::from_bytes(b"caf\xe9").unwrap());
+
+        let outcome = call_wasm_validator(&request(headers), &step).await.unwrap();
+        assert!(
+            matches!(outcome, ValidatorOutcome::Continue),
+            "{:?}",
+            outcome
+        );
+    }
+
+    #[test]
+    fn converts_values_for_components() {
+        assert!(matches!(to_wit_value(&Value::Null), WitValue::Null));
+        assert!(matches!(
+            to_wit_value(&Value::Bool(true)),
+            WitValue::Bool(true)
+        ));
+        assert!(matches!(
+            to_wit_value(&Value::Integer(-2)),
+            WitValue::Integer(-2)
+        ));
+        assert!(matches!(to_wit_value(&Value::Float(1.5)), WitValue::Float(value) if value == 1.5));
+        // a string stays a string, even if it looks like another value
+        assert!(matches!(
+            to_wit_value(&Value::String("true".to_string())),
+            WitValue::String(value) if value == "true"
+        ));
+
+        let list = Value::List(vec![
+            Value::String("main".to_string()),
+            Value::Map([("depth".to_string(), Value::Integer(1))].into()),
+        ]);
+        assert!(matches!(
+            to_wit_value(&list),
+            WitValue::Json(json) if json == r#"["main",{"depth":1}]"#
+        ));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn passes_rendered_arguments_and_with() {
+        let headers = HashMap::from([("x-branch", "main")]);
+        // a single key per map, the order of the keys of a serialized `HashMap` isn't fixed
+        let arguments = HashMap::from([("secret", Value::String("s3cret".to_string()))]);
+        let with = HashMap::from([(
+            "branches",
+            Value::List(vec![
+                Value::String("main".to_string()),
+                Value::Integer(2),
+                Value::Bool(true),
+            ]),
+        )]);
+        let mut step = plugin_step(
+            "arguments",
+            &expecting_plugin(
+                &serialize(&headers).unwrap(),
+                &serialize(&arguments).unwrap(),
+                &serialize(&with).unwrap(),
+            ),
+            PluginLimits::default(),
+        )
+        .await;
+
+        step.arguments = HashMap::from([(
+            "secret".to_string(),
+            StepValue::Secret(Secret::new("s3cret".to_string())),
+        )]);
+        step.with = HashMap::from([(
+            "branches".to_string(),
+            StepValue::List(vec![
+                StepValue::String("${{ request.headers.x-branch }}".to_string()),
+                StepValue::Integer(2),
+                StepValue::Bool(true),
+            ]),
+        )]);
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-branch", HeaderValue::from_static("main"));
+        let outcome = call_wasm_validator(&request(headers.clone()), &step)
+            .await
+            .unwrap();
+        assert!(
+            matches!(outcome, ValidatorOutcome::Continue),
+            "{:?}",
+            outcome
+        );
+
+        step.arguments = HashMap::from([(
+            "secret".to_string(),
+            StepValue::Secret(Secret::new("other".to_string())),
+        )]);
+        let outcome = call_wasm_validator(&request(headers), &step).await.unwrap();
+        assert!(
+            matches!(outcome, ValidatorOutcome::Reject(None)),
+            "{:?}",
+            outcome
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reports_plugin_killed_during_validation() {
+        let limits = PluginLimits {
+            timeout: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let step = plugin_step(
+            "validator_timeout",
+            r#"(module
+                (memory (export "memory") 1)
+                (global $heap (mut i32) (i32.const 1024))
+                (func (export "alloc") (param $len i32) (result i32)
+                    (global.get $heap)
+                    (global.set $heap (i32.add (global.get $heap) (local.get $len))))
+                (func (export "dealloc") (param i32 i32))
+                (func (export "_setup") (result i32) (i32.const 0))
+                (func (export "http_validator")
+                    (param i32 i32 i32 i32 i32 i32 i32 i32 i32 i32) (result i32)
+                    (loop (br 0))
+                    (i32.const 0)))"#,
+            limits,
+        )
+        .await;
+
+        let err = call_wasm_validator(&request(HeaderMap::new()), &step)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.downcast_ref::<LimitExceeded>(),
+            Some(&LimitExceeded::Timeout(limits.timeout))
+        );
+    }
+}
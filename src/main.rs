@@ -1,27 +1,227 @@
-use std::sync::Arc;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use config_parser::migrate::migrate;
+use config_parser::raw::{ConfigFile, ConfigVersion};
+use config_parser::vars::{ReplaceVariables, Resolvers};
 
+use crate::reload::{ActiveConfig, SharedConfig};
+use crate::scheduler::SharedHealthCheckStatus;
+
+mod actions;
+mod executor;
+mod reload;
+mod runs;
+mod scheduler;
+mod scope;
 mod server;
 
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Path of the config file.
+    #[arg(
+        long,
+        short,
+        global = true,
+        env = "WEBHOOK_HANDLER_CONFIG",
+        default_value = CONFIG_FILE
+    )]
+    config: PathBuf,
+    /// Path of the env file, `.env` is loaded if it exists and no path is set.
+    #[arg(long, global = true, env = ENV_FILE)]
+    env_file: Option<PathBuf>,
+    /// Address to listen on instead of `config.bind`, e.g. `127.0.0.1:3000`.
+    #[arg(long, global = true, env = "WEBHOOK_HANDLER_BIND")]
+    bind: Option<SocketAddr>,
+    /// `serve` is the default command.
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Serves the routes of the config file and reloads it on changes.
+    Serve,
+    /// Loads the config file and its plugins, fails if the config file is invalid.
+    Check,
+    /// Prints the config file with all variables resolved and the secrets redacted.
+    PrintConfig,
+    /// Prints the JSON Schema of the config file, e.g. for yaml-language-server.
+    Schema,
+    /// Upgrades the config file to the latest version, comments are kept.
+    Migrate {
+        /// Prints the migrated config file instead of overwriting it.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Prints the version of the webhook handler and of the config file format.
+    Version,
+}
+
+const CONFIG_FILE: &str = "./webhook_handler_demo_config.yml";
+
+/// Environment variable with the path of the env file, `.env` is used if it isn't set.
+const ENV_FILE: &str = "WEBHOOK_HANDLER_ENV_FILE";
+
+/// Loads the variables of the env file into the environment, only an env file that is set
+/// with `--env-file` or [`ENV_FILE`] has to exist.
+fn load_env_file(path: Option<&Path>) -> Result<()> {
+    match path {
+        Some(path) => {
+            dotenv::from_path(path)
+                .with_context(|| format!("Could not load the env file '{}'", path.display()))?;
+        }
+        None => match dotenv::dotenv() {
+            Ok(path) => tracing::info!("Loaded the env file '{}'", path.display()),
+            Err(err) if err.not_found() => tracing::info!("No .env file found"),
+            Err(err) => return Err(err).context("Could not load the .env file"),
+        },
+    }
+
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    dotenv::dotenv()?;
+    // logs go to stderr, so that the output of `print-config` and `schema` can be piped
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .init();
 
-    let config_raw = config_parser::raw::ConfigFile::parse("./webhook_handler_demo_config.yml")?;
-    let mut config = config_parser::internal::ConfigFileInternal::from_config(config_raw).await?;
-    config.populate_env_variables()?;
-    let config = Arc::new(config);
+    let cli = Cli::parse();
+    let env_file = cli.env_file.as_deref();
+    match cli.command {
+        None | Some(Command::Serve) => serve(&cli.config, env_file, cli.bind).await,
+        Some(Command::Check) => check(&cli.config, env_file).await,
+        Some(Command::PrintConfig) => print_config(&cli.config, env_file),
+        Some(Command::Schema) => print_schema(),
+        Some(Command::Migrate { dry_run }) => migrate_file(&cli.config, dry_run),
+        Some(Command::Version) => {
+            println!(
+                "{} {} (config version {})",
+                env!("CARGO_PKG_NAME"),
+                env!("CARGO_PKG_VERSION"),
+                ConfigVersion::LATEST
+            );
+            Ok(())
+        }
+    }
+}
+
+async fn check(path: &Path, env_file: Option<&Path>) -> Result<()> {
+    load_env_file(env_file)?;
+    ActiveConfig::load(path).await?;
+    println!("The config file '{}' is valid", path.display());
+
+    Ok(())
+}
+
+fn print_config(path: &Path, env_file: Option<&Path>) -> Result<()> {
+    load_env_file(env_file)?;
+    let mut config = ConfigFile::load(path, &actions::names())?;
+    config.replace_variables(Resolvers::global())?;
+    print!("{}", serde_yaml::to_string(&config)?);
+
+    Ok(())
+}
+
+fn print_schema() -> Result<()> {
+    let schema = config_parser::schema::config_schema(&actions::ACTIONS);
+    println!("{}", serde_json::to_string_pretty(&schema)?);
+
+    Ok(())
+}
+
+fn migrate_file(path: &Path, dry_run: bool) -> Result<()> {
+    let source = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read the config file '{}'", path.display()))?;
+    let migrated = migrate(&source)?;
+
+    if dry_run {
+        print!("{}", migrated.source);
+        return Ok(());
+    }
+    if !migrated.is_migrated() {
+        eprintln!(
+            "'{}' already has the version {}",
+            path.display(),
+            ConfigVersion::LATEST
+        );
+        return Ok(());
+    }
+
+    for deprecation in &migrated.deprecations {
+        eprintln!(
+            "{}:{}:{}: {}",
+            path.display(),
+            deprecation.line,
+            deprecation.column,
+            deprecation.message
+        );
+    }
+    std::fs::write(path, &migrated.source)
+        .with_context(|| format!("Could not write the config file '{}'", path.display()))?;
+    eprintln!(
+        "Migrated '{}' to the version {}",
+        path.display(),
+        ConfigVersion::LATEST
+    );
+
+    Ok(())
+}
+
+async fn serve(path: &Path, env_file: Option<&Path>, bind: Option<SocketAddr>) -> Result<()> {
+    load_env_file(env_file)?;
+
+    let config = SharedConfig::new(ActiveConfig::load(path).await?);
+
+    let health_check_status = SharedHealthCheckStatus::default();
 
     let server_handle = tokio::spawn({
         let config = config.clone();
+        let health_check_status = health_check_status.clone();
 
-        println!("Server is starting");
+        tracing::info!("Server is starting");
 
-        async { crate::server::start(config).await }
+        async move { crate::server::start(config, bind, health_check_status).await }
     });
 
-    server_handle.await??;
+    let scheduler_handle = tokio::spawn({
+        let config = config.clone();
+        let health_check_status = health_check_status.clone();
+
+        async { crate::scheduler::start(config, health_check_status).await }
+    });
+
+    let watcher_handle = tokio::spawn(crate::reload::watch(path.to_path_buf(), bind, config));
+
+    let server = async { server_handle.await? };
+    let scheduler = async { scheduler_handle.await? };
+    let watcher = async { watcher_handle.await? };
+    tokio::try_join!(server, scheduler, watcher)?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_bind_before_and_after_serve() {
+        let address = "127.0.0.1:8080".parse::<SocketAddr>().unwrap();
+
+        for args in [
+            &["webhook_handler", "--bind", "127.0.0.1:8080"][..],
+            &["webhook_handler", "--bind", "127.0.0.1:8080", "serve"],
+            &["webhook_handler", "serve", "--bind", "127.0.0.1:8080"],
+        ] {
+            let cli = Cli::try_parse_from(args).unwrap();
+
+            assert_eq!(cli.bind, Some(address), "{:?}", args);
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use arc_swap::ArcSwap;
+use config_parser::internal::ConfigFileInternal;
+use matchit::Router;
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, watch};
+use tracing::{debug, error, info, warn};
+
+use crate::actions;
+use crate::server::{build_router, Endpoint};
+
+/// Changes of the watched files within this time are reloaded at once, e.g. an editor that
+/// writes a file in multiple steps.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// A config with the router of its routes, both are replaced together on a reload.
+pub struct ActiveConfig {
+    pub config: ConfigFileInternal,
+    pub router: Router<Endpoint>,
+}
+
+impl ActiveConfig {
+    pub fn new(config: ConfigFileInternal) -> Result<ActiveConfig> {
+        let router = build_router(&config)?;
+
+        Ok(ActiveConfig { config, router })
+    }
+
+    pub async fn load(path: &Path) -> Result<ActiveConfig> {
+        ActiveConfig::new(ConfigFileInternal::load(path, &actions::names()).await?)
+    }
+
+    /// Like [`ActiveConfig::load`], but on a blocking thread. Compiling the wasm plugins can
+    /// take a while and would stall the requests on the same worker thread otherwise.
+    async fn load_blocking(path: &Path) -> Result<ActiveConfig> {
+        let path = path.to_path_buf();
+        let runtime = tokio::runtime::Handle::current();
+
+        tokio::task::spawn_blocking(move || runtime.block_on(ActiveConfig::load(&path))).await?
+    }
+}
+
+/// The active config, shared between the server, the scheduler and the watcher.
+///
+/// A request keeps the config it started with until it's finished, even if the config is
+/// reloaded in the meantime.
+#[derive(Clone)]
+pub struct SharedConfig {
+    active: Arc<ArcSwap<ActiveConfig>>,
+    reloaded: Arc<watch::Sender<()>>,
+}
+
+impl SharedConfig {
+    pub fn new(config: ActiveConfig) -> SharedConfig {
+        SharedConfig {
+            active: Arc::new(ArcSwap::from_pointee(config)),
+            reloaded: Arc::new(watch::Sender::new(())),
+        }
+    }
+
+    pub fn load(&self) -> Arc<ActiveConfig> {
+        self.active.load_full()
+    }
+
+    /// Is notified on every reload of the config.
+    pub fn subscribe(&self) -> watch::Receiver<()> {
+        self.reloaded.subscribe()
+    }
+
+    fn store(&self, config: ActiveConfig) {
+        self.active.store(Arc::new(config));
+        self.reloaded.send_replace(());
+    }
+}
+
+/// Files whose changes trigger a reload, the config file and the wasm plugins.
+///
+/// Their directories are watched instead of the files, as editors often replace a file instead
+/// of writing to it, which would end a watch on the file itself.
+struct WatchedFiles {
+    watcher: RecommendedWatcher,
+    files: HashSet<PathBuf>,
+    directories: HashSet<PathBuf>,
+}
+
+impl WatchedFiles {
+    fn update(&mut self, config_path: &Path, config: &ConfigFileInternal) {
+        self.files = std::iter::once(config_path.to_path_buf())
+            .chain(config.wasm_paths())
+            .map(|path| absolute(&path))
+            .collect();
+
+        let directories = self
+            .files
+            .iter()
+            .filter_map(|file| file.parent().map(Path::to_path_buf))
+            .collect::<HashSet<_>>();
+        for directory in self.directories.difference(&directories) {
+            if let Err(err) = self.watcher.unwatch(directory) {
+                debug!("Could not stop watching '{}': {}", directory.display(), err);
+            }
+        }
+        for directory in directories.difference(&self.directories) {
+            if let Err(err) = self.watcher.watch(directory, RecursiveMode::NonRecursive) {
+                warn!(
+                    "Could not watch '{}' for changes: {}",
+                    directory.display(),
+                    err
+                );
+            }
+        }
+
+        self.directories = directories;
+    }
+
+    fn contains_any(&self, event: &Event) -> bool {
+        event.paths.iter().any(|path| self.files.contains(path))
+    }
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Reloads the config file at `path` when it or one of its wasm plugins changes or on SIGHUP,
+/// `bind` is the address that overrides `config.bind`.
+///
+/// The new config is loaded in the background and only replaces the active one if it's valid,
+/// otherwise the active config is kept and the error is logged.
+pub async fn watch(path: PathBuf, bind: Option<SocketAddr>, config: SharedConfig) -> Result<()> {
+    let (sender, mut events) = mpsc::unbounded_channel();
+    let watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        let _ = sender.send(event);
+    })
+    .context("Could not watch the config file")?;
+    let mut watched = WatchedFiles {
+        watcher,
+        files: HashSet::new(),
+        directories: HashSet::new(),
+    };
+    watched.update(&path, &config.load().config);
+
+    let mut hangup = signal(SignalKind::hangup()).context("Could not listen for SIGHUP")?;
+    let listening = bind.unwrap_or(config.load().config.config.bind);
+
+    loop {
+        tokio::select! {
+            _ = hangup.recv() => info!("Received SIGHUP, reloading the config file"),
+            Some(event) = events.recv() => {
+                match event {
+                    Ok(event) if watched.contains_any(&event) => {}
+                    Ok(_) => continue,
+                    Err(err) => {
+                        warn!("Error while watching the config file: {}", err);
+                        continue;
+                    }
+                }
+
+                tokio::time::sleep(DEBOUNCE).await;
+                while events.try_recv().is_ok() {}
+
+                info!("The config file or a wasm plugin changed, reloading the config file");
+            }
+        }
+
+        match ActiveConfig::load_blocking(&path).await {
+            Ok(reloaded) => {
+                if bind.is_none() && reloaded.config.config.bind != listening {
+                    warn!(
+                        "`config.bind` changed, the server keeps listening on {} until it's restarted",
+                        listening
+                    );
+                }
+
+                watched.update(&path, &reloaded.config);
+                config.store(reloaded);
+                info!("Reloaded the config file '{}'", path.display());
+            }
+            Err(err) => error!(
+                "Could not reload the config file, keeping the active config: {:#}",
+                err
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use config_parser::raw::ConfigFile;
+
+    use super::*;
+
+    async fn active_config(source: &str) -> ActiveConfig {
+        let config = ConfigFileInternal::from_config(ConfigFile::parse(source).unwrap())
+            .await
+            .unwrap();
+
+        ActiveConfig::new(config).unwrap()
+    }
+
+    #[tokio::test]
+    async fn swaps_the_config_on_reload() {
+        let config = SharedConfig::new(active_config("version: 1.0\nconfig: {}\nroutes: []").await);
+        let reloads = config.subscribe();
+        let before = config.load();
+
+        config.store(
+            active_config(
+                "version: 1.0\nconfig: {}\nroutes:\n  - { path: /deploy, pipeline: [], steps: [] }",
+            )
+            .await,
+        );
+
+        assert!(reloads.has_changed().unwrap());
+        assert!(before.router.at("/deploy").is_err());
+        assert!(config.load().router.at("/deploy").is_ok());
+    }
+
+    async fn reloaded(reloads: &mut watch::Receiver<()>) {
+        tokio::time::timeout(Duration::from_secs(5), reloads.changed())
+            .await
+            .expect("The config wasn't reloaded")
+            .unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reloads_the_config_file() {
+        let dir =
+            std::env::temp_dir().join(format!("webhook_handler_reload_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.yml");
+        let routes = |paths: &[&str]| {
+            let routes = paths
+                .iter()
+                .map(|path| format!("\n  - {{ path: {}, pipeline: [], steps: [] }}", path))
+                .collect::<String>();
+
+            format!("version: 1.0\nconfig: {{}}\nroutes:{}", routes)
+        };
+        std::fs::write(&path, routes(&["/deploy"])).unwrap();
+
+        let config = SharedConfig::new(ActiveConfig::load(&path).await.unwrap());
+        let mut reloads = config.subscribe();
+        tokio::spawn(watch(path.clone(), None, config.clone()));
+
+        // the watcher needs a moment until it watches the directory
+        tokio::time::sleep(DEBOUNCE).await;
+        std::fs::write(&path, routes(&["/deploy", "/release"])).unwrap();
+        reloaded(&mut reloads).await;
+        assert!(config.load().router.at("/release").is_ok());
+
+        unsafe { libc::kill(libc::getpid(), libc::SIGHUP) };
+        reloaded(&mut reloads).await;
+
+        // an invalid config keeps the active one
+        std::fs::write(
+            &path,
+            "version: 1.0\nconfig: {}\nroutes: [{ path: /deploy }]",
+        )
+        .unwrap();
+        tokio::time::sleep(DEBOUNCE * 3).await;
+        assert!(!reloads.has_changed().unwrap());
+        assert!(config.load().router.at("/release").is_ok());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
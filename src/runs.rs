@@ -0,0 +1,147 @@
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::{Arc, Mutex, RwLock};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::executor::{ExecutionReport, StepReport, StepStatus};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RunOutcome {
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub succeeded: bool,
+    pub failed_step: Option<String>,
+    pub error: Option<String>,
+}
+
+impl RunOutcome {
+    fn from_report(started_at: DateTime<Utc>, report: &ExecutionReport) -> RunOutcome {
+        let (failed_step, error) = match report.failed_step() {
+            Some(StepReport {
+                name,
+                status: StepStatus::Failed(err),
+            }) => (Some(name.clone()), Some(format!("{:#}", err))),
+            _ => (None, None),
+        };
+
+        RunOutcome {
+            started_at,
+            finished_at: Utc::now(),
+            succeeded: failed_step.is_none(),
+            failed_step,
+            error,
+        }
+    }
+}
+
+#[derive(Default)]
+struct RouteRun {
+    running: tokio::sync::Mutex<()>,
+    last_outcome: RwLock<Option<RunOutcome>>,
+}
+
+/// The runs of the steps of every route, keyed by the path of the route.
+///
+/// The steps of a route run one after another, e.g. two deliveries of the same push don't
+/// build and start the same container at the same time. The routes are kept across reloads
+/// of the config, so a run of the old config still blocks the next run of the new one.
+#[derive(Default)]
+pub struct RouteRuns {
+    routes: Mutex<HashMap<String, Arc<RouteRun>>>,
+}
+
+impl RouteRuns {
+    /// Waits until the previous runs of `path` are finished, then runs `steps` and keeps its
+    /// outcome. The runs are started in the order they are queued.
+    pub async fn run(&self, path: &str, steps: impl Future<Output = ExecutionReport>) {
+        let route = self
+            .routes
+            .lock()
+            .unwrap()
+            .entry(path.to_string())
+            .or_default()
+            .clone();
+
+        let _running = route.running.lock().await;
+        let started_at = Utc::now();
+        let report = steps.await;
+
+        *route.last_outcome.write().unwrap() = Some(RunOutcome::from_report(started_at, &report));
+    }
+
+    /// The outcome of the last run of every route that ran since the start.
+    pub fn last_outcomes(&self) -> BTreeMap<String, RunOutcome> {
+        self.routes
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(path, route)| {
+                let outcome = route.last_outcome.read().unwrap().clone()?;
+
+                Some((path.clone(), outcome))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn runs_the_steps_of_a_route_one_after_another() {
+        let runs = RouteRuns::default();
+        let running = AtomicUsize::new(0);
+        let most_running = AtomicUsize::new(0);
+
+        let run = |path: &'static str, failed: bool| {
+            let (runs, running, most_running) = (&runs, &running, &most_running);
+
+            async move {
+                runs.run(path, async {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    most_running.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+
+                    ExecutionReport {
+                        steps: vec![StepReport {
+                            name: "deploy".to_string(),
+                            status: match failed {
+                                true => StepStatus::Failed(anyhow::anyhow!("exit status 1")),
+                                false => StepStatus::Success,
+                            },
+                        }],
+                    }
+                })
+                .await
+            }
+        };
+
+        tokio::join!(
+            run("/deploy", false),
+            run("/deploy", false),
+            run("/deploy", true)
+        );
+        assert_eq!(most_running.load(Ordering::SeqCst), 1);
+
+        most_running.store(0, Ordering::SeqCst);
+        tokio::join!(run("/deploy", false), run("/release", false));
+        assert_eq!(most_running.load(Ordering::SeqCst), 2);
+
+        let outcomes = runs.last_outcomes();
+        assert_eq!(outcomes.keys().collect::<Vec<_>>(), ["/deploy", "/release"]);
+        assert!(outcomes["/deploy"].succeeded);
+
+        run("/deploy", true).await;
+        let outcome = &runs.last_outcomes()["/deploy"];
+        assert!(!outcome.succeeded);
+        assert_eq!(outcome.failed_step.as_deref(), Some("deploy"));
+        assert_eq!(outcome.error.as_deref(), Some("exit status 1"));
+    }
+}
@@ -0,0 +1,93 @@
+use std::sync::{Arc, RwLock};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::executor::{execute_steps, ExecutionReport, StepReport, StepStatus};
+use crate::reload::SharedConfig;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthCheckOutcome {
+    pub finished_at: DateTime<Utc>,
+    pub healthy: bool,
+    pub failed_step: Option<String>,
+    pub error: Option<String>,
+}
+
+impl HealthCheckOutcome {
+    fn from_report(report: &ExecutionReport) -> HealthCheckOutcome {
+        let (failed_step, error) = match report.failed_step() {
+            Some(StepReport {
+                name,
+                status: StepStatus::Failed(err),
+            }) => (Some(name.clone()), Some(format!("{:#}", err))),
+            _ => (None, None),
+        };
+
+        HealthCheckOutcome {
+            finished_at: Utc::now(),
+            healthy: failed_step.is_none(),
+            failed_step,
+            error,
+        }
+    }
+}
+
+/// State of the health check, shared between the scheduler and the server.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HealthCheckStatus {
+    /// `None` until the health check ran for the first time.
+    pub last_outcome: Option<HealthCheckOutcome>,
+    pub next_run: Option<DateTime<Utc>>,
+}
+
+pub type SharedHealthCheckStatus = Arc<RwLock<HealthCheckStatus>>;
+
+/// Runs the health check steps on every tick of `health_check.period`, the next run is
+/// rescheduled whenever the config is reloaded.
+pub async fn start(config: SharedConfig, status: SharedHealthCheckStatus) -> Result<()> {
+    let mut reloads = config.subscribe();
+
+    loop {
+        reloads.borrow_and_update();
+        let active = config.load();
+
+        let next_run = active
+            .config
+            .health_check
+            .as_ref()
+            .and_then(|health_check| health_check.period.upcoming(Utc).next());
+        status.write().unwrap().next_run = next_run;
+
+        let (Some(health_check), Some(next_run)) = (&active.config.health_check, next_run) else {
+            if active.config.health_check.is_some() {
+                info!("The health check has no upcoming runs, waiting for a new config");
+            }
+
+            reloads.changed().await?;
+            continue;
+        };
+
+        info!("Next health check at {}", next_run);
+
+        tokio::select! {
+            _ = tokio::time::sleep((next_run - Utc::now()).to_std().unwrap_or_default()) => {}
+            reloaded = reloads.changed() => {
+                reloaded?;
+                continue;
+            }
+        }
+
+        let report = execute_steps(&health_check.steps, None).await;
+        let outcome = HealthCheckOutcome::from_report(&report);
+
+        match &outcome.failed_step {
+            None => info!("Health check passed"),
+            Some(failed_step) => warn!("Health check failed at the step '{}'", failed_step),
+        }
+
+        status.write().unwrap().last_outcome = Some(outcome);
+    }
+}
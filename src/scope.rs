@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use anyhow::{bail, Context, Result};
+use config_parser::expression::{Template, Value};
+use config_parser::value::StepValue;
+use config_parser::vars::Resolvers;
+use shared::value;
+
+use crate::executor::WrappedRequest;
+
+/// Values a step sets for the steps after it, referenced with `steps.<id>.outputs.<name>`.
+pub type Outputs = HashMap<String, String>;
+
+/// Everything the expressions of a step can reference while the steps are executed.
+pub struct Scope<'a> {
+    request: Option<&'a WrappedRequest<'a>>,
+    steps: &'a HashMap<String, Outputs>,
+    /// The body of the request parsed as json, only parsed if `request.body.json` is used.
+    json: OnceLock<Option<serde_json::Value>>,
+}
+
+impl<'a> Scope<'a> {
+    pub fn new(
+        request: Option<&'a WrappedRequest<'a>>,
+        steps: &'a HashMap<String, Outputs>,
+    ) -> Scope<'a> {
+        Scope {
+            request,
+            steps,
+            json: OnceLock::new(),
+        }
+    }
+
+    fn json(&self, request: &WrappedRequest) -> Option<&serde_json::Value> {
+        self.json
+            .get_or_init(|| serde_json::from_slice(request.body).ok())
+            .as_ref()
+    }
+
+    fn lookup(&self, path: &[String]) -> Result<Value> {
+        let segments = path.iter().map(String::as_str).collect::<Vec<_>>();
+
+        let request = match segments.as_slice() {
+            ["steps", id, "outputs", name] => {
+                return Ok(self
+                    .steps
+                    .get(*id)
+                    .and_then(|outputs| outputs.get(*name))
+                    .map_or(Value::Null, |output| Value::String(output.clone())));
+            }
+            ["request" | "route", ..] => self.request.with_context(|| {
+                format!(
+                    "'{}' can only be used in the steps of a route",
+                    path.join(".")
+                )
+            })?,
+            _ => return Resolvers::global().lookup(path),
+        };
+
+        Ok(match segments.as_slice() {
+            ["request", "body"] => {
+                Value::String(String::from_utf8_lossy(request.body).into_owned())
+            }
+            ["request", "body", "json", keys @ ..] => {
+                let mut json = self
+                    .json(request)
+                    .context("The body of the request is not valid json")?;
+
+                for key in keys {
+                    let next = match json {
+                        serde_json::Value::Array(items) => {
+                            key.parse::<usize>().ok().and_then(|index| items.get(index))
+                        }
+                        json => json.get(key),
+                    };
+
+                    match next {
+                        Some(next) => json = next,
+                        None => return Ok(Value::Null),
+                    }
+                }
+
+                json.clone().into()
+            }
+            ["request", "headers", name] => {
+                request.headers.get(*name).map_or(Value::Null, |value| {
+                    Value::String(String::from_utf8_lossy(value.as_bytes()).into_owned())
+                })
+            }
+            ["route", "params", name] => request
+                .params
+                .get(*name)
+                .map_or(Value::Null, |param| Value::String(param.clone())),
+            _ => bail!("Unknown variable '{}'", path.join(".")),
+        })
+    }
+
+    pub fn render(&self, template: &str) -> Result<String> {
+        Template::parse(template)?.render(&|path| self.lookup(path))
+    }
+
+    /// Renders every string of `value` and reveals the secrets.
+    fn render_value(&self, step_value: &StepValue) -> Result<value::Value> {
+        Ok(match step_value {
+            StepValue::Null => value::Value::Null,
+            StepValue::Bool(bool) => value::Value::Bool(*bool),
+            StepValue::Integer(integer) => value::Value::Integer(*integer),
+            StepValue::Float(float) => value::Value::Float(*float),
+            StepValue::String(template) => value::Value::String(self.render(template)?),
+            // already resolved when the config was loaded
+            StepValue::Secret(secret) => value::Value::String(secret.reveal().clone()),
+            StepValue::List(items) => value::Value::List(
+                items
+                    .iter()
+                    .map(|item| self.render_value(item))
+                    .collect::<Result<_>>()?,
+            ),
+            StepValue::Map(entries) => value::Value::Map(self.render_entries(entries)?),
+        })
+    }
+
+    fn render_entries<'m, C>(
+        &self,
+        entries: impl IntoIterator<Item = (&'m String, &'m StepValue)>,
+    ) -> Result<C>
+    where
+        C: FromIterator<(String, value::Value)>,
+    {
+        entries
+            .into_iter()
+            .map(|(key, value)| {
+                let value = self
+                    .render_value(value)
+                    .with_context(|| format!("Could not evaluate '{}'", key))?;
+
+                Ok((key.clone(), value))
+            })
+            .collect()
+    }
+
+    /// Renders all values of `map`, e.g. the `with` of a step, and reveals the secrets.
+    /// The result is only meant to be handed to a plugin or an action.
+    pub fn render_map(
+        &self,
+        map: &HashMap<String, StepValue>,
+    ) -> Result<HashMap<String, value::Value>> {
+        self.render_entries(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::HeaderMap;
+    use shared::http::{HttpMethod, HttpVersion};
+
+    use super::*;
+
+    fn request() -> WrappedRequest<'static> {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-github-event", "push".parse().unwrap());
+
+        WrappedRequest {
+            body: br#"{"ref":"refs/heads/main","commits":[{"id":"abc"}]}"#,
+            headers,
+            method: HttpMethod::POST,
+            version: HttpVersion::Http1_1,
+            params: HashMap::from([("project".to_string(), "website".to_string())]),
+        }
+    }
+
+    #[test]
+    fn renders_request_and_steps() {
+        let request = request();
+        let steps = HashMap::from([(
+            "build".to_string(),
+            Outputs::from([("image".to_string(), "website:1".to_string())]),
+        )]);
+        let scope = Scope::new(Some(&request), &steps);
+
+        assert_eq!(
+            scope
+                .render(
+                    "${{ route.params.project }} ${{ request.headers.x-github-event }} \
+                     ${{ request.body.json.ref }} ${{ request.body.json.commits.0.id }} \
+                     ${{ steps.build.outputs.image }}"
+                )
+                .unwrap(),
+            "website push refs/heads/main abc website:1"
+        );
+        assert_eq!(
+            scope
+                .render("${{ request.body.json.ref == 'refs/heads/main' }}")
+                .unwrap(),
+            "true"
+        );
+        assert_eq!(
+            scope
+                .render("${{ request.headers.x-missing || 'none' }}")
+                .unwrap(),
+            "none"
+        );
+    }
+
+    #[test]
+    fn request_is_only_available_in_routes() {
+        let steps = HashMap::new();
+        let scope = Scope::new(None, &steps);
+
+        assert_eq!(
+            scope.render("${{ request.body }}").unwrap_err().to_string(),
+            "'request.body' can only be used in the steps of a route"
+        );
+    }
+
+    #[test]
+    fn renders_nested_values() {
+        let request = request();
+        let steps = HashMap::new();
+        let scope = Scope::new(Some(&request), &steps);
+
+        let with: HashMap<String, StepValue> = serde_json::from_str(
+            r#"{
+                "networks": ["internal", "${{ route.params.project }}"],
+                "auto_remove": true,
+                "env": { "EVENT": "${{ request.headers.x-github-event }}", "RETRIES": 3 }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            scope.render_map(&with).unwrap(),
+            HashMap::from([
+                ("networks".to_string(), vec!["internal", "website"].into()),
+                ("auto_remove".to_string(), true.into()),
+                (
+                    "env".to_string(),
+                    value::Value::Map(
+                        [
+                            ("EVENT".to_string(), "push".into()),
+                            ("RETRIES".to_string(), value::Value::Integer(3)),
+                        ]
+                        .into()
+                    )
+                ),
+            ])
+        );
+    }
+}
@@ -1,32 +1,50 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::net::SocketAddr;
 use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use config_parser::internal::ConfigFileInternal;
-use glue::error::CustomError;
-use glue::wasm_memory::WasmMemory;
 use http_body_util::{BodyExt, Full};
 use hyper::body::{Body, Bytes, Incoming};
-use hyper::header::HeaderValue;
+use hyper::header::CONTENT_TYPE;
 use hyper::server::conn::http1;
 use hyper::service::service_fn;
-use hyper::{HeaderMap, Request, Response, StatusCode};
+use hyper::{Request, Response, StatusCode};
 use hyper_util::rt::TokioIo;
+use matchit::Router;
+use serde::Serialize;
 use shared::http::{HttpMethod, HttpVersion};
-use shared::interop::serialize;
+use shared::ValidatorResponse;
 use tokio::net::TcpListener;
-use tokio::sync::Mutex;
-use wasmtime::{Instance, Store};
-use wasmtime_wasi::WasiP1Ctx;
+use tracing::{debug, error, info, warn};
+
+use crate::executor::{call_wasm_validator, execute_steps, ValidatorOutcome, WrappedRequest};
+use crate::reload::{ActiveConfig, SharedConfig};
+use crate::runs::{RouteRuns, RunOutcome};
+use crate::scheduler::{HealthCheckStatus, SharedHealthCheckStatus};
 
 const MAX_BODY_SIZE: u64 = 1 << 16; // 64kB
 
-struct WrappedRequest<'a> {
-    body: &'a [u8],
-    headers: HeaderMap<HeaderValue>,
-    method: HttpMethod,
-    version: HttpVersion,
+#[derive(Debug, Clone, Copy)]
+pub enum Endpoint {
+    /// Index into [`ConfigFileInternal::routes`].
+    Route(usize),
+    HealthCheck,
+}
+
+struct State {
+    config: SharedConfig,
+    health_check_status: SharedHealthCheckStatus,
+    runs: Arc<RouteRuns>,
+}
+
+/// The response of the health check path.
+#[derive(Serialize)]
+struct Status {
+    #[serde(flatten)]
+    health_check: HealthCheckStatus,
+    /// The outcome of the last run of the steps of every route.
+    routes: BTreeMap<String, RunOutcome>,
 }
 
 async fn not_found(request: &Request<Incoming>) -> Result<Response<Full<Bytes>>> {
@@ -39,63 +57,72 @@ async fn not_found(request: &Request<Incoming>) -> Result<Response<Full<Bytes>>>
         ))))?)
 }
 
-async fn call_wasm_validator<'a>(
-    request: &WrappedRequest<'a>,
-    instance: Arc<Instance>,
-    store: Arc<Mutex<Store<WasiP1Ctx>>>,
-) -> Result<()> {
-    let fct_http_validator = instance
-        .get_typed_func::<(i32, i32, i32, i32, i32, i32, i32, i32), i32>(
-            &mut *store.lock().await,
-            "http_validator",
-        )?;
-
-    let headers = request
-        .headers
-        .iter()
-        .map(|(name, value)| (name.to_string(), value.to_str().unwrap()))
-        .collect::<HashMap<String, &str>>();
-
-    let hashmap = WasmMemory::new(&serialize(&headers)?, instance.clone(), store.clone()).await?;
-    let arguments = WasmMemory::new(
-        &serialize(&{
-            let mut map = HashMap::<&str, &str>::new();
-            map.insert("secret", "It's a Secret to Everybody");
-            map
-        })?, // TODO use value from config
-        instance.clone(),
-        store.clone(),
-    )
-    .await?;
-
-    let body_wasm = WasmMemory::new(&request.body, instance.clone(), store.clone()).await?;
-
-    let request_result = fct_http_validator
-        .call_async(
-            &mut *store.lock().await,
-            (
-                body_wasm.ptr(),
-                body_wasm.len() as i32,
-                hashmap.ptr(),
-                hashmap.len() as i32,
-                request.method as i32,
-                request.version as i32,
-                arguments.ptr(),
-                arguments.len() as i32,
-            ),
-        )
-        .await?;
+async fn health_check_status(state: &State) -> Result<Response<Full<Bytes>>> {
+    let status = Status {
+        health_check: state.health_check_status.read().unwrap().clone(),
+        routes: state.runs.last_outcomes(),
+    };
+
+    let status_code = match &status.health_check.last_outcome {
+        Some(outcome) if !outcome.healthy => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::OK,
+    };
+
+    Ok(Response::builder()
+        .status(status_code)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from(serde_json::to_vec(&status)?)))?)
+}
+
+/// The response a validator rejected the request with. An invalid response, e.g. with an unknown
+/// status or an invalid header, is logged and replaced with a `500`.
+fn validator_response(
+    validator: &str,
+    response: ValidatorResponse,
+) -> Result<Response<Full<Bytes>>> {
+    let built = StatusCode::from_u16(response.status)
+        .ok()
+        .filter(|status| !status.is_informational())
+        .with_context(|| format!("Invalid status {}", response.status))
+        .and_then(|status| {
+            let mut builder = Response::builder().status(status);
+
+            for (name, value) in response.headers {
+                builder = builder.header(name, value);
+            }
+
+            Ok(builder.body(Full::new(Bytes::from(response.body)))?)
+        });
 
-    let err_msg = CustomError::from_wasm(instance.clone(), store.clone()).await?;
-    dbg!(request_result, err_msg);
+    match built {
+        Ok(response) => Ok(response),
+        Err(err) => {
+            error!(
+                validator,
+                "The validator returned an invalid response: {:#}", err
+            );
 
-    Ok(())
+            Ok(Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Full::new(Bytes::from(format!(
+                    "The validator '{}' returned an invalid response\n",
+                    validator
+                ))))?)
+        }
+    }
 }
 
+/// Runs the pipeline of the route at `index` and answers with `202 Accepted` once it passed,
+/// the steps of the route are then queued behind the running steps of the same route.
 async fn validator_request(
     request: Request<Incoming>,
-    config: Arc<ConfigFileInternal>,
+    runs: Arc<RouteRuns>,
+    active: Arc<ActiveConfig>,
+    index: usize,
+    params: HashMap<String, String>,
 ) -> Result<Response<Full<Bytes>>> {
+    let route = &active.config.routes[index];
+
     let upper = request.body().size_hint().upper().unwrap_or(u64::MAX);
     if upper > MAX_BODY_SIZE {
         return Ok(Response::builder()
@@ -110,63 +137,209 @@ async fn validator_request(
     let method = HttpMethod::try_from(request.method())?;
     let version = HttpVersion::try_from(request.version())?;
 
+    let body = request.collect().await?.to_bytes();
     let request = WrappedRequest {
-        body: &request.collect().await?.to_bytes(),
+        body: &body,
         headers,
         method,
         version,
+        params,
     };
 
-    for validator in &config.route.pipeline {
-        let instance = validator.instance.clone().unwrap();
-        let store = validator.store.clone().unwrap();
+    for validator in &route.pipeline {
+        debug!(validator = validator.display_name(), "Calling validator");
+
+        let outcome = match call_wasm_validator(&request, validator).await {
+            Ok(outcome) => outcome,
+            Err(err) => {
+                error!(
+                    validator = validator.display_name(),
+                    "Validator failed: {:#}", err
+                );
+
+                return Ok(Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Full::new(Bytes::from(format!(
+                        "The validator '{}' failed\n",
+                        validator.display_name()
+                    ))))?);
+            }
+        };
 
-        dbg!(validator.id);
+        if let ValidatorOutcome::Reject(response) = outcome {
+            info!(
+                validator = validator.display_name(),
+                "Request rejected by the validator"
+            );
 
-        call_wasm_validator(&request, instance, store).await?;
+            return match response {
+                Some(response) => validator_response(validator.display_name(), response),
+                None => Ok(Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Full::new(Bytes::from(format!(
+                        "Request rejected by the validator '{}'\n",
+                        validator.display_name()
+                    ))))?),
+            };
+        }
     }
 
+    // the steps don't hold the response open and aren't cancelled if the caller disconnects,
+    // their errors are logged and kept as the last outcome of the route
+    let WrappedRequest {
+        headers,
+        method,
+        version,
+        params,
+        ..
+    } = request;
+    tokio::spawn(async move {
+        let route = &active.config.routes[index];
+        let request = WrappedRequest {
+            body: &body,
+            headers,
+            method,
+            version,
+            params,
+        };
+
+        runs.run(&route.path, async {
+            let report = execute_steps(&route.steps, Some(&request)).await;
+            match report.failed_step() {
+                None => info!(route = route.path, "Finished the steps"),
+                Some(step) => warn!(
+                    route = route.path,
+                    "Stopped at the failed step '{}'", step.name
+                ),
+            }
+
+            report
+        })
+        .await;
+    });
+
     Ok(Response::builder()
-        .status(StatusCode::OK)
+        .status(StatusCode::ACCEPTED)
         .body(Full::new(Bytes::new()))?)
 }
 
 async fn handle_request(
-    config: Arc<ConfigFileInternal>,
+    state: Arc<State>,
     request: Request<Incoming>,
 ) -> Result<Response<Full<Bytes>>> {
-    if request.uri().path() == config.route.path {
-        validator_request(request, config).await
-    } else {
-        not_found(&request).await
+    let active = state.config.load();
+    let matched = active.router.at(request.uri().path()).ok().map(|matched| {
+        let params = matched
+            .params
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<HashMap<_, _>>();
+
+        (*matched.value, params)
+    });
+
+    match matched {
+        Some((Endpoint::Route(index), params)) => {
+            validator_request(request, state.runs.clone(), active, index, params).await
+        }
+        Some((Endpoint::HealthCheck, _)) => health_check_status(&state).await,
+        None => not_found(&request).await,
+    }
+}
+
+pub fn build_router(config: &ConfigFileInternal) -> Result<Router<Endpoint>> {
+    let mut router = Router::new();
+
+    for (index, route) in config.routes.iter().enumerate() {
+        router
+            .insert(route.path.clone(), Endpoint::Route(index))
+            .with_context(|| format!("Invalid route path: '{}'", route.path))?;
+    }
+
+    if let Some(path) = config
+        .health_check
+        .as_ref()
+        .and_then(|health_check| health_check.path.as_ref())
+    {
+        router
+            .insert(path.clone(), Endpoint::HealthCheck)
+            .with_context(|| format!("Invalid health check path: '{}'", path))?;
     }
+
+    Ok(router)
 }
 
-pub async fn start(config: Arc<ConfigFileInternal>) -> Result<()> {
-    let addr = SocketAddr::from(([0, 0, 0, 0], config.config.expose));
+/// Listens on `bind` or else on `config.bind` of the config at the start, the routes of a
+/// reloaded config are used for the next request.
+pub async fn start(
+    config: SharedConfig,
+    bind: Option<SocketAddr>,
+    health_check_status: SharedHealthCheckStatus,
+) -> Result<()> {
+    let addr = bind.unwrap_or(config.load().config.config.bind);
+    let state = Arc::new(State {
+        config,
+        health_check_status,
+        runs: Arc::default(),
+    });
 
     let listener = TcpListener::bind(addr).await?;
+    info!("Listening on {}", addr);
 
     loop {
         let (stream, _) = listener.accept().await?;
 
-        println!("Got a new connection");
+        debug!("Got a new connection");
 
         let io = TokioIo::new(stream);
-        let config = config.clone();
+        let state = state.clone();
 
         tokio::spawn(async move {
             if let Err(err) = http1::Builder::new()
                 .serve_connection(
                     io,
-                    service_fn(|request| async { handle_request(config.clone(), request).await }),
+                    service_fn(|request| async { handle_request(state.clone(), request).await }),
                 )
                 .await
             {
-                eprintln!("Error serving connection: {:?}", err);
+                error!("Error serving connection: {:?}", err);
             }
         });
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rejected(status: u16, headers: &[(&str, &str)]) -> Response<Full<Bytes>> {
+        validator_response(
+            "validator",
+            ValidatorResponse {
+                status,
+                headers: headers
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect(),
+                body: b"rejected".to_vec(),
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn replaces_invalid_validator_responses() {
+        let response = rejected(401, &[("content-type", "text/plain")]);
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/plain");
 
-    Ok(())
+        for response in [
+            rejected(1000, &[]),
+            rejected(101, &[]),
+            rejected(401, &[("invalid name", "value")]),
+            rejected(401, &[("x-value", "line\nbreak")]),
+        ] {
+            assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
 }
@@ -0,0 +1,41 @@
+use std::cell::RefCell;
+
+use shared::constants::{MAX_ERR_MSG_LEN, NO_ERROR};
+
+thread_local! {
+    static ERR_NO: RefCell<i32> = const { RefCell::new(0) };
+    static ERR_MSG: RefCell<[u8; MAX_ERR_MSG_LEN]> = const { RefCell::new([0; MAX_ERR_MSG_LEN]) };
+}
+
+pub fn err_clear() {
+    set_err_no(NO_ERROR as i32);
+
+    ERR_MSG.with_borrow_mut(|item| *item = [0; MAX_ERR_MSG_LEN]);
+}
+
+pub fn set_err_no(err: i32) {
+    ERR_NO.set(err);
+}
+
+pub fn get_err_no() -> i32 {
+    ERR_NO.with_borrow(|item| *item)
+}
+
+/// Stores the message as a nul-terminated string, messages longer than
+/// [`MAX_ERR_MSG_LEN`] - 1 bytes are truncated.
+pub fn set_err_msg_str(msg: &str) {
+    ERR_MSG.with_borrow_mut(|item| {
+        let bytes = msg.as_bytes();
+        let len = bytes.len().min(item.len() - 1);
+
+        item[..len].copy_from_slice(&bytes[..len]);
+        item[len] = b'\0';
+    });
+}
+
+pub fn get_err_msg() -> *const u8 {
+    ERR_MSG.with(|err_msg| {
+        let borrowed_msg = err_msg.borrow();
+        borrowed_msg.as_ptr()
+    })
+}
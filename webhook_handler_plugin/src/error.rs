@@ -0,0 +1,66 @@
+use std::fmt::{Debug, Display, Formatter};
+
+use shared::ValidatorResponse;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Error returned by a validator.
+///
+/// Any error that converts into an [`anyhow::Error`] can be used with `?`, the host then rejects
+/// the request with a generic response. Use [`Error::reject`] to choose the response yourself.
+pub enum Error {
+    Reject(ValidatorResponse),
+    Other(anyhow::Error),
+}
+
+impl Error {
+    pub fn reject(status: u16, body: impl Into<Vec<u8>>) -> Self {
+        Error::Reject(ValidatorResponse {
+            status,
+            headers: Vec::new(),
+            body: body.into(),
+        })
+    }
+
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        if let Error::Reject(response) = &mut self {
+            response.headers.push((name.into(), value.into()));
+        }
+
+        self
+    }
+}
+
+impl<E: Into<anyhow::Error>> From<E> for Error {
+    fn from(value: E) -> Self {
+        Error::Other(value.into())
+    }
+}
+
+impl Debug for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Reject(response) => write!(
+                f,
+                "Reject({}, {:?})",
+                response.status,
+                String::from_utf8_lossy(&response.body)
+            ),
+            Error::Other(err) => write!(f, "{:?}", err),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Reject(response) => write!(
+                f,
+                "rejected with status {}: {}",
+                response.status,
+                String::from_utf8_lossy(&response.body)
+            ),
+            Error::Other(err) => write!(f, "{}", err),
+        }
+    }
+}
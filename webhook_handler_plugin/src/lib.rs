@@ -0,0 +1,138 @@
+//! SDK for validator plugins that are loaded as a core wasm module.
+//!
+//! ```ignore
+//! use webhook_handler_plugin::{validator, Arguments, Error, Request, Result};
+//!
+//! #[validator]
+//! fn validate(request: Request, arguments: Arguments) -> Result<()> {
+//!     if request.header("x-token") != arguments.get("token") {
+//!         return Err(Error::reject(403, "Invalid token\n"));
+//!     }
+//!
+//!     Ok(())
+//! }
+//! ```
+//!
+//! The [`validator`] attribute generates all functions that the host expects to be exported.
+
+pub use shared::http::{HttpMethod, HttpVersion};
+pub use shared::value::Value;
+pub use shared::ValidatorResponse;
+pub use webhook_handler_plugin_macros::validator;
+
+pub use crate::error::{Error, Result};
+pub use crate::request::{Arguments, Request};
+
+mod err_no;
+mod error;
+mod memory;
+mod request;
+mod response;
+mod setup;
+mod util;
+
+/// Used by the code generated from [`validator`], not public api.
+#[doc(hidden)]
+pub mod __private {
+    use serde::Deserialize;
+    use shared::interop::deserialize;
+    pub use shared::MiddlewareResult;
+    use tracing::info;
+
+    pub use crate::err_no::{err_clear, get_err_msg, get_err_no};
+    use crate::err_no::{set_err_msg_str, set_err_no};
+    pub use crate::memory::{alloc, dealloc};
+    pub use crate::response::{get_response_len, get_response_ptr};
+    use crate::response::{response_clear, set_response};
+    pub use crate::setup::{setup, SetupResult};
+    use crate::util::get_slice_from_ptr_and_len_safe;
+    use crate::{Arguments, Error, HttpMethod, HttpVersion, Request, Result};
+
+    fn deserialize_or_set_err<'a, T: Deserialize<'a>>(slice: &'a [u8], err_no: i32) -> Option<T> {
+        match deserialize(slice) {
+            Ok(item) => Some(item),
+            Err(err) => {
+                set_err_no(err_no);
+                set_err_msg_str(&format!("Deserialize error: {:?}", err));
+
+                None
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn http_validator<F>(
+        body_ptr: *const u8,
+        body_len: u32,
+        headers_ptr: *const u8,
+        headers_len: u32,
+        http_method: HttpMethod,
+        http_version: HttpVersion,
+        arguments_ptr: *const u8,
+        arguments_len: u32,
+        with_ptr: *const u8,
+        with_len: u32,
+        validator: F,
+    ) -> MiddlewareResult
+    where
+        F: for<'a> FnOnce(Request<'a>, Arguments) -> Result<()>,
+    {
+        err_clear();
+        response_clear();
+
+        let Ok(body_slice) = get_slice_from_ptr_and_len_safe(body_ptr, body_len) else {
+            return MiddlewareResult::Error;
+        };
+        let Ok(headers_slice) = get_slice_from_ptr_and_len_safe(headers_ptr, headers_len) else {
+            return MiddlewareResult::Error;
+        };
+        let Ok(arguments_slice) = get_slice_from_ptr_and_len_safe(arguments_ptr, arguments_len)
+        else {
+            return MiddlewareResult::Error;
+        };
+        let Ok(with_slice) = get_slice_from_ptr_and_len_safe(with_ptr, with_len) else {
+            return MiddlewareResult::Error;
+        };
+
+        let Some(headers) = deserialize_or_set_err(headers_slice, -2) else {
+            return MiddlewareResult::Error;
+        };
+        let Some(arguments) = deserialize_or_set_err(arguments_slice, -3) else {
+            return MiddlewareResult::Error;
+        };
+        let Some(with) = deserialize_or_set_err(with_slice, -4) else {
+            return MiddlewareResult::Error;
+        };
+
+        info!("Calling the internal validator");
+
+        let request = Request {
+            body: body_slice,
+            headers,
+            method: http_method,
+            version: http_version,
+        };
+
+        match validator(request, Arguments { arguments, with }) {
+            Ok(_) => MiddlewareResult::Continue,
+            Err(Error::Reject(response)) => {
+                set_err_no(1);
+                set_err_msg_str(&format!(
+                    "validator: rejected with status {}",
+                    response.status
+                ));
+
+                match set_response(&response) {
+                    Ok(_) => MiddlewareResult::Reject,
+                    Err(_) => MiddlewareResult::Error,
+                }
+            }
+            Err(Error::Other(err)) => {
+                set_err_no(1);
+                set_err_msg_str(&format!("validator: {:?}", err));
+
+                MiddlewareResult::Error
+            }
+        }
+    }
+}
@@ -1,6 +1,5 @@
 /// Allocate memory into the module's linear memory
 /// and return the offset to the start of the block.
-#[no_mangle]
 pub fn alloc(len: usize) -> *mut u8 {
     // ! Copied from https://radu-matei.com/blog/practical-guide-to-wasm-memory/#passing-arrays-to-rust-webassembly-modules
 
@@ -15,10 +14,12 @@ pub fn alloc(len: usize) -> *mut u8 {
     std::mem::forget(buf);
     // return the pointer so the runtime
     // can write data at this offset
-    return ptr;
+    ptr
 }
 
-#[no_mangle]
+/// # Safety
+///
+/// `ptr` and `size` must describe a block previously returned by [`alloc`].
 pub unsafe fn dealloc(ptr: *mut u8, size: usize) {
     // ! Copied from https://radu-matei.com/blog/practical-guide-to-wasm-memory/#passing-arrays-to-rust-webassembly-modules
     let data = Vec::from_raw_parts(ptr, size, size);
@@ -0,0 +1,46 @@
+use std::collections::HashMap;
+
+use shared::http::{HttpMethod, HttpVersion};
+use shared::value::Value;
+
+/// The incoming http request that should be validated.
+#[derive(Debug)]
+pub struct Request<'a> {
+    pub body: &'a [u8],
+    pub headers: HashMap<&'a str, &'a str>,
+    pub method: HttpMethod,
+    pub version: HttpVersion,
+}
+
+impl<'a> Request<'a> {
+    pub fn header(&self, name: &str) -> Option<&'a str> {
+        self.headers.get(name).copied()
+    }
+}
+
+/// The `arguments` and `with` maps of the step from the config, with all expressions evaluated.
+#[derive(Debug)]
+pub struct Arguments {
+    pub arguments: HashMap<String, Value>,
+    pub with: HashMap<String, Value>,
+}
+
+impl Arguments {
+    /// The argument `key` if it's a string, use [`Arguments::value`] for other values.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.value(key).and_then(Value::as_str)
+    }
+
+    pub fn value(&self, key: &str) -> Option<&Value> {
+        self.arguments.get(key)
+    }
+
+    /// The key `key` of `with` if it's a string, use [`Arguments::with_value`] for other values.
+    pub fn with(&self, key: &str) -> Option<&str> {
+        self.with_value(key).and_then(Value::as_str)
+    }
+
+    pub fn with_value(&self, key: &str) -> Option<&Value> {
+        self.with.get(key)
+    }
+}
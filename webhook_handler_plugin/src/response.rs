@@ -0,0 +1,30 @@
+use std::cell::RefCell;
+
+use shared::interop::serialize;
+use shared::ValidatorResponse;
+
+thread_local! {
+    static RESPONSE: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+pub fn response_clear() {
+    RESPONSE.with_borrow_mut(|item| item.clear());
+}
+
+/// Sets the response that the host sends back to the caller if the validator
+/// returns [`shared::MiddlewareResult::Reject`].
+pub fn set_response(response: &ValidatorResponse) -> anyhow::Result<()> {
+    let serialized = serialize(response)?;
+
+    RESPONSE.with_borrow_mut(|item| *item = serialized);
+
+    Ok(())
+}
+
+pub fn get_response_ptr() -> *const u8 {
+    RESPONSE.with_borrow(|item| item.as_ptr())
+}
+
+pub fn get_response_len() -> u32 {
+    RESPONSE.with_borrow(|item| item.len() as u32)
+}
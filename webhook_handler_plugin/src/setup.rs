@@ -10,8 +10,7 @@ pub enum SetupResult {
     Error,
 }
 
-#[no_mangle]
-pub extern "C" fn _setup() -> SetupResult {
+pub fn setup() -> SetupResult {
     err_clear();
 
     let fmt_layer = tracing_subscriber::fmt::layer()
@@ -0,0 +1,106 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, ItemFn};
+
+/// Turns `fn(Request, Arguments) -> Result<()>` into a validator plugin by generating all
+/// functions that the host expects to be exported from the wasm module.
+#[proc_macro_attribute]
+pub fn validator(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return syn::Error::new(
+            proc_macro2::Span::call_site(),
+            "#[validator] doesn't take any arguments",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    let function = parse_macro_input!(item as ItemFn);
+    let ident = &function.sig.ident;
+
+    if function.sig.inputs.len() != 2 {
+        return syn::Error::new_spanned(
+            &function.sig.inputs,
+            "a validator must take exactly two arguments: `Request` and `Arguments`",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    quote! {
+        #function
+
+        #[no_mangle]
+        pub extern "C" fn alloc(len: usize) -> *mut u8 {
+            ::webhook_handler_plugin::__private::alloc(len)
+        }
+
+        /// # Safety
+        ///
+        /// `ptr` and `size` must describe a block previously returned by `alloc`.
+        #[no_mangle]
+        pub unsafe extern "C" fn dealloc(ptr: *mut u8, size: usize) {
+            ::webhook_handler_plugin::__private::dealloc(ptr, size)
+        }
+
+        #[no_mangle]
+        pub extern "C" fn err_clear() {
+            ::webhook_handler_plugin::__private::err_clear()
+        }
+
+        #[no_mangle]
+        pub extern "C" fn get_err_no() -> i32 {
+            ::webhook_handler_plugin::__private::get_err_no()
+        }
+
+        #[no_mangle]
+        pub extern "C" fn get_err_msg() -> *const u8 {
+            ::webhook_handler_plugin::__private::get_err_msg()
+        }
+
+        #[no_mangle]
+        pub extern "C" fn get_response_ptr() -> *const u8 {
+            ::webhook_handler_plugin::__private::get_response_ptr()
+        }
+
+        #[no_mangle]
+        pub extern "C" fn get_response_len() -> u32 {
+            ::webhook_handler_plugin::__private::get_response_len()
+        }
+
+        #[no_mangle]
+        pub extern "C" fn _setup() -> ::webhook_handler_plugin::__private::SetupResult {
+            ::webhook_handler_plugin::__private::setup()
+        }
+
+        #[no_mangle]
+        #[allow(clippy::too_many_arguments)]
+        pub extern "C" fn http_validator(
+            body_ptr: *const u8,
+            body_len: u32,
+            headers_ptr: *const u8,
+            headers_len: u32,
+            http_method: ::webhook_handler_plugin::HttpMethod,
+            http_version: ::webhook_handler_plugin::HttpVersion,
+            arguments_ptr: *const u8,
+            arguments_len: u32,
+            with_ptr: *const u8,
+            with_len: u32,
+        ) -> ::webhook_handler_plugin::__private::MiddlewareResult {
+            ::webhook_handler_plugin::__private::http_validator(
+                body_ptr,
+                body_len,
+                headers_ptr,
+                headers_len,
+                http_method,
+                http_version,
+                arguments_ptr,
+                arguments_len,
+                with_ptr,
+                with_len,
+                #ident,
+            )
+        }
+    }
+    .into()
+}